@@ -4,15 +4,31 @@ struct CameraUniform {
 };
 @group(0) @binding(0) var<uniform> camera: CameraUniform;
 
-struct InstanceInput {
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
-}
+struct Block {
+    colors: array<vec4<f32>, 6>,
+    flags: vec4<u32>,
+};
 
-struct RenderInput {
-    @location(9) model_render: u32,
+struct BlockRegistry {
+    blocks: array<Block, 128>,
+};
+@group(1) @binding(0) var<uniform> registry: BlockRegistry;
+
+struct ChunkUniform {
+    origin: vec4<i32>,
+};
+@group(3) @binding(0) var<uniform> chunk: ChunkUniform;
+
+let FLAG_EMISSIVE: u32 = 4u;
+
+// Fragment
+@group(2) @binding(0) var block_textures: texture_2d_array<f32>;
+@group(2) @binding(1) var block_sampler: sampler;
+
+struct InstanceInput {
+    // x, y, z in 5 bits each, local to the chunk, then the face in 3 bits
+    // and the block in the rest
+    @location(5) data: u32,
 }
 
 struct VertexInput {
@@ -22,57 +38,73 @@ struct VertexInput {
 struct VertexOutput {
     @builtin(position) model_position: vec4<f32>,
     // 
-    @location(0) color: vec3<f32>,
-    @location(1) position: vec3<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) @interpolate(flat) layer: u32,
+}
+
+// Places the unit quad on its side of the voxel, matches `face::ORIGINS`
+// and `face::AXES`
+fn face_corner(face: u32, corner: vec2<f32>) -> vec3<f32> {
+    var origins = array<vec3<f32>, 6>(
+        vec3<f32>(0.0, 0.0, 0.0),
+        vec3<f32>(0.0, 0.0, 1.0),
+        vec3<f32>(1.0, 0.0, 1.0),
+        vec3<f32>(1.0, 0.0, 0.0),
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(0.0, 0.0, 1.0),
+    );
+    var x_axes = array<vec3<f32>, 6>(
+        vec3<f32>(1.0, 0.0, 0.0),
+        vec3<f32>(0.0, 0.0, -1.0),
+        vec3<f32>(-1.0, 0.0, 0.0),
+        vec3<f32>(0.0, 0.0, 1.0),
+        vec3<f32>(1.0, 0.0, 0.0),
+        vec3<f32>(1.0, 0.0, 0.0),
+    );
+    var y_axes = array<vec3<f32>, 6>(
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(0.0, 0.0, 1.0),
+        vec3<f32>(0.0, 0.0, -1.0),
+    );
+    return origins[face] + corner.x * x_axes[face] + corner.y * y_axes[face];
+}
+
+// Texture array layer of a face, one byte per face in the block flags
+fn texture_layer(flags: vec4<u32>, face: u32) -> u32 {
+    return (flags[1u + face / 4u] >> (face % 4u * 8u)) & 255u;
 }
 
 @vertex
-fn vs_main(model: VertexInput, instance: InstanceInput, render: RenderInput) -> VertexOutput {
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
     var out: VertexOutput;
 
-    if render.model_render == 1u {   
-        let model_matrix = mat4x4<f32>(
-            instance.model_matrix_0,
-            instance.model_matrix_1,
-            instance.model_matrix_2,
-            instance.model_matrix_3,
-        );
-
-        out.model_position = camera.view_proj * model_matrix *  vec4<f32>(model.position, 1.0);
-        out.color = vec3<f32>(0.3, 0.7, 0.4);
-        out.position = model.position;
-    }
-    
-    return out;
-}
+    let local = vec3<u32>(instance.data, instance.data >> 5u, instance.data >> 10u) & vec3<u32>(31u);
+    let face = (instance.data >> 15u) & 7u;
+    let block = instance.data >> 18u;
+
+    let voxel = vec3<f32>(chunk.origin.xyz + vec3<i32>(local));
+    let world_position = voxel + face_corner(face, model.position.xy);
+    out.model_position = camera.view_proj * vec4<f32>(world_position, 1.0);
 
-fn is_border(x: f32, y: f32, z: f32, border: f32, dimension: f32) -> bool {
-    let min = border;
-    let max = dimension - border;
-    let x_min = min - x;
-    let x_max = max - x;
-    let y_min = min - y;
-    let y_max = max - y;
-    let z_min = min - z;
-    let z_max = max - z;
-
-    if  x_min > 0.0 && y_min > 0.0 || x_max < 0.0 && y_max < 0.0 ||
-        x_min > 0.0 && z_min > 0.0 || x_max < 0.0 && z_max < 0.0 ||
-        y_min > 0.0 && z_min > 0.0 || y_max < 0.0 && z_max < 0.0 ||
-        x_max < 0.0 && y_min > 0.0 || x_min > 0.0 && y_max < 0.0 ||
-        x_max < 0.0 && z_min > 0.0 || x_min > 0.0 && z_max < 0.0 ||
-        y_max < 0.0 && z_min > 0.0 || y_min > 0.0 && z_max < 0.0 {
-        return true;
-    } else {
-        return false;
+    let color = registry.blocks[block].colors[face];
+    var shade = 1.0;
+    if (registry.blocks[block].flags.x & FLAG_EMISSIVE) == 0u {
+        // Up, sides, down
+        shade = select(select(0.8, 0.6, face == 5u), 1.0, face == 4u);
     }
+    out.color = vec4<f32>(color.rgb * shade, color.a);
+    // Upright on the sides
+    out.uv = vec2<f32>(model.position.x, 1.0 - model.position.y);
+    out.layer = texture_layer(registry.blocks[block].flags, face);
+
+    return out;
 }
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    if is_border(in.position.x, in.position.y, in.position.z, 0.02, 1.0) {
-        return vec4<f32>(in.color.x + 0.1, in.color.y + 0.1, in.color.z + 0.1, 1.0);
-    } else {
-        return vec4<f32>(in.color, 1.0);
-    }
+    return in.color * textureSample(block_textures, block_sampler, in.uv, i32(in.layer));
 }
@@ -0,0 +1,122 @@
+// Tests the bounds of every draw against the frustum and the depth pyramid of
+// the previous frame and writes the indirect draw arguments.
+
+struct Draw {
+    min: vec3<f32>,
+    index_count: u32,
+    max: vec3<f32>,
+    instance_count: u32,
+};
+
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+struct Cull {
+    // Of the frame the depth pyramid was built from
+    previous_view_proj: mat4x4<f32>,
+    planes: array<vec4<f32>, 6>,
+    draw_count: u32,
+    // Zero until there is a depth pyramid
+    hi_z_levels: u32,
+    hi_z_size: vec2<u32>,
+};
+
+@group(0) @binding(0) var<uniform> cull: Cull;
+@group(0) @binding(1) var<storage, read> draws: array<Draw>;
+@group(0) @binding(2) var<storage, read_write> indirect: array<DrawIndexedIndirect>;
+// Levels packed one after another, each half the size of the one before
+@group(0) @binding(3) var<storage, read> hi_z: array<f32>;
+
+fn level_size(level: u32) -> vec2<u32> {
+    return max(cull.hi_z_size >> vec2<u32>(level), vec2<u32>(1u));
+}
+
+fn in_frustum(low: vec3<f32>, high: vec3<f32>) -> bool {
+    for (var i = 0; i < 6; i = i + 1) {
+        let plane = cull.planes[i];
+        // The corner furthest along the normal
+        let corner = select(low, high, plane.xyz >= vec3<f32>(0.0));
+        if dot(plane.xyz, corner) + plane.w < 0.0 {
+            return false;
+        }
+    }
+    return true;
+}
+
+fn occluded(low: vec3<f32>, high: vec3<f32>) -> bool {
+    if cull.hi_z_levels == 0u {
+        return false;
+    }
+
+    var rect_min = vec2<f32>(1.0);
+    var rect_max = vec2<f32>(0.0);
+    var depth = 1.0;
+    for (var i = 0u; i < 8u; i = i + 1u) {
+        let corner = select(low, high, vec3<bool>((i & 1u) != 0u, (i & 2u) != 0u, (i & 4u) != 0u));
+        let clip = cull.previous_view_proj * vec4<f32>(corner, 1.0);
+        // Reaches behind the previous eye, where the pyramid knows nothing
+        if clip.w <= 0.0 || clip.z < 0.0 {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        rect_min = min(rect_min, uv);
+        rect_max = max(rect_max, uv);
+        depth = min(depth, ndc.z);
+    }
+
+    // Texels of the full size level the box covers
+    let size = vec2<f32>(cull.hi_z_size);
+    let last = cull.hi_z_size - 1u;
+    let texel_min = min(vec2<u32>(clamp(rect_min, vec2<f32>(0.0), vec2<f32>(1.0)) * size), last);
+    let texel_max = min(vec2<u32>(clamp(rect_max, vec2<f32>(0.0), vec2<f32>(1.0)) * size), last);
+
+    // The smallest level where they fall into at most 2x2 texels
+    let span = texel_max - texel_min;
+    var level = 0u;
+    var offset = 0u;
+    loop {
+        if level + 1u >= cull.hi_z_levels || ((span.x >> level) == 0u && (span.y >> level) == 0u) {
+            break;
+        }
+        let skipped = level_size(level);
+        offset = offset + skipped.x * skipped.y;
+        level = level + 1u;
+    }
+
+    // Odd sizes fold the remainder into the last texel of the next level
+    let dimensions = level_size(level);
+    let a = min(texel_min >> vec2<u32>(level), dimensions - 1u);
+    let b = min(texel_max >> vec2<u32>(level), dimensions - 1u);
+    let farthest = max(
+        max(hi_z[offset + a.x + a.y * dimensions.x], hi_z[offset + b.x + a.y * dimensions.x]),
+        max(hi_z[offset + a.x + b.y * dimensions.x], hi_z[offset + b.x + b.y * dimensions.x]),
+    );
+
+    return depth > farthest;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= cull.draw_count {
+        return;
+    }
+
+    let draw = draws[i];
+    var visible = draw.instance_count > 0u && in_frustum(draw.min, draw.max);
+    if visible {
+        visible = !occluded(draw.min, draw.max);
+    }
+
+    indirect[i].index_count = draw.index_count;
+    indirect[i].instance_count = select(0u, draw.instance_count, visible);
+    indirect[i].first_index = 0u;
+    indirect[i].base_vertex = 0;
+    indirect[i].first_instance = 0u;
+}
@@ -0,0 +1,45 @@
+// Depth pyramid for occlusion culling, every texel holds the farthest depth of
+// the texels it covers in the level below. The levels are packed one after
+// another into a buffer, the GL backend can't bind storage textures.
+
+struct Level {
+    source_offset: u32,
+    offset: u32,
+    source_size: vec2<u32>,
+    size: vec2<u32>,
+    _padding: vec2<u32>,
+};
+
+@group(0) @binding(0) var<uniform> level: Level;
+@group(0) @binding(1) var<storage, read_write> pyramid: array<f32>;
+// Bound as a float texture, GLSL can't load from depth textures
+@group(0) @binding(2) var depth: texture_2d<f32>;
+
+@compute @workgroup_size(8, 8)
+fn cs_copy(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= level.size.x || id.y >= level.size.y {
+        return;
+    }
+
+    pyramid[level.offset + id.x + id.y * level.size.x] = textureLoad(depth, vec2<i32>(id.xy), 0).r;
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= level.size.x || id.y >= level.size.y {
+        return;
+    }
+
+    // The last row and column also take the remainder of odd sizes
+    let first = id.xy * 2u;
+    let last = select(first + 1u, level.source_size - 1u, id.xy == level.size - 1u);
+
+    var farthest = 0.0;
+    for (var y = first.y; y <= last.y; y = y + 1u) {
+        for (var x = first.x; x <= last.x; x = x + 1u) {
+            farthest = max(farthest, pyramid[level.source_offset + x + y * level.source_size.x]);
+        }
+    }
+
+    pyramid[level.offset + id.x + id.y * level.size.x] = farthest;
+}
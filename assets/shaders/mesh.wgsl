@@ -0,0 +1,65 @@
+// Vertex
+struct CameraUniform {
+    view_proj: mat4x4<f32>
+};
+@group(0) @binding(0) var<uniform> camera: CameraUniform;
+
+struct Block {
+    colors: array<vec4<f32>, 6>,
+    flags: vec4<u32>,
+};
+
+struct BlockRegistry {
+    blocks: array<Block, 128>,
+};
+@group(1) @binding(0) var<uniform> registry: BlockRegistry;
+
+let FLAG_EMISSIVE: u32 = 4u;
+
+// Fragment
+@group(2) @binding(0) var block_textures: texture_2d_array<f32>;
+@group(2) @binding(1) var block_sampler: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) block: u32,
+    @location(3) face: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) model_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) @interpolate(flat) layer: u32,
+}
+
+// Texture array layer of a face, one byte per face in the block flags
+fn texture_layer(flags: vec4<u32>, face: u32) -> u32 {
+    return (flags[1u + face / 4u] >> (face % 4u * 8u)) & 255u;
+}
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+
+    out.model_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+
+    let color = registry.blocks[model.block].colors[model.face];
+    var shade = 1.0;
+    if (registry.blocks[model.block].flags.x & FLAG_EMISSIVE) == 0u {
+        // Up, sides, down
+        shade = select(select(0.8, 0.6, model.face == 5u), 1.0, model.face == 4u);
+    }
+    out.color = vec4<f32>(color.rgb * shade, color.a);
+    out.uv = model.uv;
+    out.layer = texture_layer(registry.blocks[model.block].flags, model.face);
+
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Merged quads span several blocks, the sampler repeats the texture for every unit cell
+    return in.color * textureSample(block_textures, block_sampler, in.uv, i32(in.layer));
+}
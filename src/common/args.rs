@@ -0,0 +1,11 @@
+// Command line arguments, flags like `--fullscreen` and options like `--seed 42`.
+
+/// The value following the option `name`.
+pub fn arg_value(name: &str) -> Option<String> {
+    std::env::args().skip_while(|arg| arg != name).nth(1)
+}
+
+/// Whether the flag `name` was passed.
+pub fn has_flag(name: &str) -> bool {
+    std::env::args().any(|arg| arg == name)
+}
@@ -15,12 +15,21 @@ impl BundleManager {
         }
     }
 
-    pub fn get_bundles(&self) -> &[wgpu::RenderBundle] {
-        &*self.bundles
+    pub fn get_visible_bundles(&self, indices: &[usize]) -> Vec<&wgpu::RenderBundle> {
+        indices.iter().map(|index| &self.bundles[*index]).collect()
     }
 
-    pub fn push_bundle(&mut self, bundle: wgpu::RenderBundle) {
+    pub fn len(&self) -> usize {
+        self.bundles.len()
+    }
+
+    pub fn push_bundle(&mut self, bundle: wgpu::RenderBundle) -> usize {
         self.bundles.push(bundle);
+        self.bundles.len() - 1
+    }
+
+    pub fn set_bundle(&mut self, index: usize, bundle: wgpu::RenderBundle) {
+        self.bundles[index] = bundle;
     }
 
     pub fn _push_bundles(&mut self, bundles: Vec<wgpu::RenderBundle>) {
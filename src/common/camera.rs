@@ -3,11 +3,16 @@ use wgpu::util::DeviceExt;
 
 pub struct CameraManager {
     pub camera: Camera,
+    // Camera at the previous tick, rendering interpolates towards the current one
+    pub previous_camera: Camera,
     pub camera_uniform: CameraUniform,
     pub camera_buffer: wgpu::Buffer,
     pub camera_bind_group_layout: wgpu::BindGroupLayout,
     pub camera_bind_group: wgpu::BindGroup,
     pub camera_controller: CameraController,
+    // Of the camera last uploaded, for culling what it doesn't see
+    pub view_proj: cgmath::Matrix4<f32>,
+    pub frustum: super::frustum::Frustum,
 }
 
 impl CameraManager {
@@ -20,11 +25,15 @@ impl CameraManager {
             fovy: 45.0,
             znear: 0.1,
             zfar: 100.0,
+            projection: Projection::Perspective,
         };
 
         let mut camera_uniform = CameraUniform::new();
         camera_uniform.update_view_proj(&camera);
 
+        let view_proj = camera.build_view_projection_matrix();
+        let frustum = super::frustum::Frustum::from_matrix(view_proj);
+
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Buffer - Camera"),
             contents: bytemuck::cast_slice(&[camera_uniform]),
@@ -55,17 +64,45 @@ impl CameraManager {
             }],
         });
 
-        let camera_controller = CameraController::new(0.2);
+        // Units per second
+        let camera_controller = CameraController::new(12.0);
 
         Self {
             camera,
+            previous_camera: camera,
             camera_uniform,
             camera_buffer,
             camera_bind_group_layout,
             camera_bind_group,
             camera_controller,
+            view_proj,
+            frustum,
         }
     }
+
+    /// Advances the camera by one simulation tick of `dt` seconds, see
+    /// `CameraController::update_camera` for `ground`.
+    pub fn update<F>(&mut self, dt: f32, ground: F)
+    where
+        F: Fn(i32, i32) -> Option<i32>,
+    {
+        self.previous_camera = self.camera;
+        self.camera_controller
+            .update_camera(&mut self.camera, dt, ground);
+    }
+
+    /// Uploads the camera `alpha` of the way from the previous to the current tick.
+    pub fn update_uniform(&mut self, queue: &wgpu::Queue, alpha: f32) {
+        let camera = self.previous_camera.interpolate(&self.camera, alpha);
+        self.camera_uniform.update_view_proj(&camera);
+        self.view_proj = camera.build_view_projection_matrix();
+        self.frustum = super::frustum::Frustum::from_matrix(self.view_proj);
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+    }
 }
 
 #[rustfmt::skip]
@@ -94,6 +131,27 @@ impl CameraUniform {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Projection {
+    Perspective,
+    // Parallel projection, sized like the perspective view at the target
+    Orthographic,
+    // Orthographic from a fixed pitch, rotating in 90° steps
+    Isometric,
+}
+
+impl Projection {
+    /// The projection to switch to at runtime.
+    pub fn next(self) -> Self {
+        match self {
+            Projection::Perspective => Projection::Orthographic,
+            Projection::Orthographic => Projection::Isometric,
+            Projection::Isometric => Projection::Perspective,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
     pub target: cgmath::Point3<f32>,
@@ -102,18 +160,154 @@ pub struct Camera {
     pub fovy: f32,
     pub znear: f32,
     pub zfar: f32,
+    pub projection: Projection,
 }
 
 impl Camera {
     pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
         let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        let proj = match self.projection {
+            Projection::Perspective => {
+                cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar)
+            }
+            Projection::Orthographic | Projection::Isometric => {
+                use cgmath::Angle;
+                use cgmath::InnerSpace;
+
+                // Zooming moves the eye, so the distance to the target sets the size
+                let half_height = (self.target - self.eye).magnitude()
+                    * cgmath::Deg::tan(cgmath::Deg(self.fovy / 2.0));
+                let half_width = half_height * self.aspect;
+                // Nothing gets closer than the eye, so the near plane can go
+                // behind it and keep the terrain between eye and target
+                cgmath::ortho(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    -self.zfar,
+                    self.zfar,
+                )
+            }
+        };
 
         OPENGL_TO_WGPU_MATRIX * proj * view
     }
+
+    pub fn interpolate(&self, next: &Camera, alpha: f32) -> Camera {
+        Camera {
+            eye: self.eye + (next.eye - self.eye) * alpha,
+            target: self.target + (next.target - self.target) * alpha,
+            ..*next
+        }
+    }
+}
+
+// Radians per pixel of mouse motion in free-fly mode
+const MOUSE_SENSITIVITY: f32 = 0.002;
+// Stops short of straight up or down, where the view direction and up vector align
+const MAX_PITCH: f32 = 89.0 * std::f32::consts::PI / 180.0;
+const SPRINT_FACTOR: f32 = 4.0;
+
+// Orbit limits, the pitch keeps the ground in view
+const MIN_DISTANCE: f32 = 4.0;
+const MAX_DISTANCE: f32 = 60.0;
+const MIN_ORBIT_PITCH: f32 = 10.0 * std::f32::consts::PI / 180.0;
+const MAX_ORBIT_PITCH: f32 = 85.0 * std::f32::consts::PI / 180.0;
+// Looks down the diagonal of a cube, atan(1 / sqrt(2))
+const ISOMETRIC_PITCH: f32 = 0.615_479_7;
+// Distance factor per scroll wheel line
+const ZOOM_STEP: f32 = 1.15;
+// Touchpads scroll in pixels
+const PIXELS_PER_LINE: f64 = 40.0;
+// Radians per second while Q or E is held
+const ROTATION_SPEED: f32 = 120.0 * std::f32::consts::PI / 180.0;
+// Radians per pixel of middle-dragging
+const DRAG_SENSITIVITY: f32 = 0.005;
+// Pixels from the window border that pan the camera
+const EDGE_PAN_MARGIN: f64 = 16.0;
+// Panning is `speed` at this distance, faster when zoomed out
+const PAN_REFERENCE_DISTANCE: f32 = 10.0;
+// How quickly rotation, zoom and ground height reach their goals, per second
+const DAMPING: f32 = 12.0;
+// How quickly panning speeds up and coasts to a stop, per second
+const PAN_DAMPING: f32 = 6.0;
+// Height of the eye above the ground it is over, so hills don't swallow it
+const MIN_EYE_CLEARANCE: f32 = 1.5;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    // Pans over the ground and orbits around the target
+    Orbit,
+    // Mouse-look, moving along the view direction
+    FreeFly,
+}
+
+impl CameraMode {
+    pub fn toggled(self) -> Self {
+        match self {
+            CameraMode::Orbit => CameraMode::FreeFly,
+            CameraMode::FreeFly => CameraMode::Orbit,
+        }
+    }
+}
+
+// Fraction of the way to move towards a goal this tick, independent of the tick rate
+fn smoothing(rate: f32, dt: f32) -> f32 {
+    1.0 - (-rate * dt).exp()
+}
+
+// Orbit around a target on the ground. Input moves the goals, the current
+// values follow them smoothly.
+struct Orbit {
+    target: cgmath::Point3<f32>,
+    // Radians, yaw of the eye around the target from +x
+    yaw: f32,
+    pitch: f32,
+    distance: f32,
+    goal_yaw: f32,
+    goal_pitch: f32,
+    goal_distance: f32,
+    // Panning velocity in units per second
+    velocity: cgmath::Vector3<f32>,
+}
+
+impl Orbit {
+    fn from_camera(camera: &Camera) -> Self {
+        use cgmath::InnerSpace;
+        use cgmath::Zero;
+
+        let offset = camera.eye - camera.target;
+        let distance = offset.magnitude().max(f32::EPSILON);
+        let yaw = offset.z.atan2(offset.x);
+        let pitch = (offset.y / distance).asin();
+
+        Self {
+            target: camera.target,
+            yaw,
+            pitch,
+            distance,
+            goal_yaw: yaw,
+            goal_pitch: pitch.clamp(MIN_ORBIT_PITCH, MAX_ORBIT_PITCH),
+            goal_distance: distance.clamp(MIN_DISTANCE, MAX_DISTANCE),
+            velocity: cgmath::Vector3::zero(),
+        }
+    }
+
+    // Eye position relative to the target
+    fn offset(&self) -> cgmath::Vector3<f32> {
+        cgmath::vec3(
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.sin(),
+        ) * self.distance
+    }
 }
 
 pub struct CameraController {
+    pub mode: CameraMode,
+    // Whether the cursor at the window border pans the orbit camera
+    pub edge_pan: bool,
     speed: f32,
     is_forward_pressed: bool,
     is_backward_pressed: bool,
@@ -121,14 +315,31 @@ pub struct CameraController {
     is_right_pressed: bool,
     is_rotation_left_pressed: bool,
     is_rotation_right_pressed: bool,
-    is_zoom_in_pressed: bool,
-    is_zoom_out_pressed: bool,
+    is_up_pressed: bool,
+    is_down_pressed: bool,
+    is_sprint_pressed: bool,
+    is_dragging: bool,
     reset: bool,
+    // Q/E presses since the last tick, isometric views turn a quarter per press
+    rotation_steps: i32,
+    // Free-fly view direction in radians, yaw around the y axis from +x
+    yaw: f32,
+    pitch: f32,
+    // Mouse motion since the last tick
+    mouse_delta: (f64, f64),
+    // Scroll wheel lines since the last tick
+    zoom: f32,
+    // Panning direction from the cursor at the window border, x right and y down
+    edge: (f32, f32),
+    // Derived from the camera on the first orbit tick
+    orbit: Option<Orbit>,
 }
 
 impl CameraController {
     pub fn new(speed: f32) -> Self {
         Self {
+            mode: CameraMode::Orbit,
+            edge_pan: true,
             speed,
             is_forward_pressed: false,
             is_backward_pressed: false,
@@ -136,13 +347,50 @@ impl CameraController {
             is_right_pressed: false,
             is_rotation_left_pressed: false,
             is_rotation_right_pressed: false,
-            is_zoom_in_pressed: false,
-            is_zoom_out_pressed: false,
+            is_up_pressed: false,
+            is_down_pressed: false,
+            is_sprint_pressed: false,
+            is_dragging: false,
             reset: false,
+            rotation_steps: 0,
+            yaw: 0.0,
+            pitch: 0.0,
+            mouse_delta: (0.0, 0.0),
+            zoom: 0.0,
+            edge: (0.0, 0.0),
+            orbit: None,
+        }
+    }
+
+    /// Switches the mode, both modes start from where the camera looks.
+    pub fn set_mode(&mut self, mode: CameraMode, camera: &Camera) {
+        use cgmath::InnerSpace;
+
+        if mode == CameraMode::FreeFly {
+            let forward = (camera.target - camera.eye).normalize();
+            self.yaw = forward.z.atan2(forward.x);
+            self.pitch = forward.y.asin().clamp(-MAX_PITCH, MAX_PITCH);
+        }
+        self.orbit = None;
+        self.is_dragging = false;
+        self.mouse_delta = (0.0, 0.0);
+        self.rotation_steps = 0;
+        self.mode = mode;
+    }
+
+    /// Raw mouse motion, unaffected by the cursor hitting the window edge.
+    pub fn process_mouse_motion(&mut self, delta: (f64, f64)) {
+        if self.mode == CameraMode::FreeFly || self.is_dragging {
+            self.mouse_delta.0 += delta.0;
+            self.mouse_delta.1 += delta.1;
         }
     }
 
-    pub fn process_events(&mut self, event: &winit::event::WindowEvent) -> bool {
+    pub fn process_events(
+        &mut self,
+        event: &winit::event::WindowEvent,
+        window_size: winit::dpi::PhysicalSize<u32>,
+    ) -> bool {
         match event {
             winit::event::WindowEvent::KeyboardInput {
                 input:
@@ -172,10 +420,22 @@ impl CameraController {
                         true
                     }
                     winit::event::VirtualKeyCode::Q => {
+                        if is_pressed
+                            && !self.is_rotation_left_pressed
+                            && self.mode == CameraMode::Orbit
+                        {
+                            self.rotation_steps -= 1;
+                        }
                         self.is_rotation_left_pressed = is_pressed;
                         true
                     }
                     winit::event::VirtualKeyCode::E => {
+                        if is_pressed
+                            && !self.is_rotation_right_pressed
+                            && self.mode == CameraMode::Orbit
+                        {
+                            self.rotation_steps += 1;
+                        }
                         self.is_rotation_right_pressed = is_pressed;
                         true
                     }
@@ -183,94 +443,331 @@ impl CameraController {
                         self.reset = is_pressed;
                         true
                     }
-                    _ => false,
-                }
-            }
-            winit::event::WindowEvent::MouseInput { button, state, .. } => {
-                let is_pressed = *state == winit::event::ElementState::Pressed;
-                match button {
-                    winit::event::MouseButton::Left => {
-                        self.is_zoom_in_pressed = is_pressed;
+                    winit::event::VirtualKeyCode::Space => {
+                        self.is_up_pressed = is_pressed;
                         true
                     }
-                    winit::event::MouseButton::Right => {
-                        self.is_zoom_out_pressed = is_pressed;
+                    winit::event::VirtualKeyCode::LControl => {
+                        self.is_down_pressed = is_pressed;
+                        true
+                    }
+                    winit::event::VirtualKeyCode::LShift => {
+                        self.is_sprint_pressed = is_pressed;
                         true
                     }
                     _ => false,
                 }
             }
+            winit::event::WindowEvent::MouseWheel { delta, .. }
+                if self.mode == CameraMode::Orbit =>
+            {
+                self.zoom += match delta {
+                    winit::event::MouseScrollDelta::LineDelta(_, y) => *y,
+                    winit::event::MouseScrollDelta::PixelDelta(position) => {
+                        (position.y / PIXELS_PER_LINE) as f32
+                    }
+                };
+                true
+            }
+            winit::event::WindowEvent::MouseInput {
+                button: winit::event::MouseButton::Middle,
+                state,
+                ..
+            } if self.mode == CameraMode::Orbit => {
+                self.is_dragging = *state == winit::event::ElementState::Pressed;
+                true
+            }
+            // Not consumed, other systems may want the cursor too
+            winit::event::WindowEvent::CursorMoved { position, .. } => {
+                let edge = |position: f64, size: u32| {
+                    if position < EDGE_PAN_MARGIN {
+                        -1.0
+                    } else if position >= size as f64 - EDGE_PAN_MARGIN {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                };
+                self.edge = (
+                    edge(position.x, window_size.width),
+                    edge(position.y, window_size.height),
+                );
+                false
+            }
+            winit::event::WindowEvent::CursorLeft { .. } => {
+                self.edge = (0.0, 0.0);
+                false
+            }
             _ => false,
         }
     }
 
-    pub fn update_camera(&self, camera: &mut Camera) {
-        use cgmath::Angle;
+    /// Advances the camera by `dt` seconds. `ground` is the height of the
+    /// topmost block in a column, which the orbit target follows.
+    pub fn update_camera<F>(&mut self, camera: &mut Camera, dt: f32, ground: F)
+    where
+        F: Fn(i32, i32) -> Option<i32>,
+    {
+        match self.mode {
+            CameraMode::Orbit => self.update_orbit(camera, dt, ground),
+            CameraMode::FreeFly => self.update_free_fly(camera, dt),
+        }
+    }
+
+    fn update_free_fly(&mut self, camera: &mut Camera, dt: f32) {
         use cgmath::InnerSpace;
+        use cgmath::Zero;
 
-        let forward = camera.target - camera.eye;
-        let forward_norm = forward.normalize();
+        // Keeps the orbit distance for switching back
+        let distance = (camera.target - camera.eye).magnitude().max(1.0);
+
+        let (dx, dy) = std::mem::take(&mut self.mouse_delta);
+        self.yaw += dx as f32 * MOUSE_SENSITIVITY;
+        self.pitch = (self.pitch - dy as f32 * MOUSE_SENSITIVITY).clamp(-MAX_PITCH, MAX_PITCH);
+
+        let forward = cgmath::vec3(
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.sin(),
+        );
+        let right = forward.cross(camera.up).normalize();
+
+        let mut direction = cgmath::Vector3::zero();
+        if self.is_forward_pressed {
+            direction += forward;
+        }
+        if self.is_backward_pressed {
+            direction -= forward;
+        }
+        if self.is_right_pressed {
+            direction += right;
+        }
+        if self.is_left_pressed {
+            direction -= right;
+        }
+        if self.is_up_pressed {
+            direction += camera.up;
+        }
+        if self.is_down_pressed {
+            direction -= camera.up;
+        }
+
+        let mut speed = self.speed * dt;
+        if self.is_sprint_pressed {
+            speed *= SPRINT_FACTOR;
+        }
+        if direction.magnitude2() > 0.0 {
+            camera.eye += direction.normalize() * speed;
+        }
+
+        camera.target = camera.eye + forward * distance;
+    }
+
+    fn update_orbit<F>(&mut self, camera: &mut Camera, dt: f32, ground: F)
+    where
+        F: Fn(i32, i32) -> Option<i32>,
+    {
+        use cgmath::InnerSpace;
+        use cgmath::Zero;
+
+        let orbit = self.orbit.get_or_insert_with(|| Orbit::from_camera(camera));
+
+        let (dx, dy) = std::mem::take(&mut self.mouse_delta);
+        let rotation_steps = std::mem::take(&mut self.rotation_steps);
+        if camera.projection == Projection::Isometric {
+            // Snaps to the nearest diagonal, which also eases in after switching
+            let quarter = std::f32::consts::FRAC_PI_2;
+            let eighth = std::f32::consts::FRAC_PI_4;
+            orbit.goal_yaw = ((orbit.goal_yaw - eighth) / quarter).round() * quarter
+                + eighth
+                + rotation_steps as f32 * quarter;
+            orbit.goal_pitch = ISOMETRIC_PITCH;
+        } else {
+            if self.is_dragging {
+                orbit.goal_yaw += dx as f32 * DRAG_SENSITIVITY;
+                orbit.goal_pitch = (orbit.goal_pitch + dy as f32 * DRAG_SENSITIVITY)
+                    .clamp(MIN_ORBIT_PITCH, MAX_ORBIT_PITCH);
+            }
+            if self.is_rotation_right_pressed {
+                orbit.goal_yaw += ROTATION_SPEED * dt;
+            }
+            if self.is_rotation_left_pressed {
+                orbit.goal_yaw -= ROTATION_SPEED * dt;
+            }
+            if self.reset {
+                // Looking along +x, through the nearest turn so it doesn't spin around
+                let tau = std::f32::consts::TAU;
+                let pi = std::f32::consts::PI;
+                orbit.goal_yaw = pi + ((orbit.goal_yaw - pi) / tau).round() * tau;
+            }
+        }
 
-        let right = forward_norm.cross(camera.up);
-        let right_norm = right.normalize();
+        let zoom = std::mem::take(&mut self.zoom);
+        orbit.goal_distance =
+            (orbit.goal_distance * ZOOM_STEP.powf(-zoom)).clamp(MIN_DISTANCE, MAX_DISTANCE);
 
+        // Panning on the ground plane, relative to the view
+        let forward = -cgmath::vec3(orbit.yaw.cos(), 0.0, orbit.yaw.sin());
+        let right = forward.cross(camera.up);
+
+        let mut direction = cgmath::Vector3::zero();
         if self.is_forward_pressed {
-            camera.eye.x += forward_norm.x * self.speed;
-            camera.eye.z += forward_norm.z * self.speed;
-            camera.target.x += forward_norm.x * self.speed;
-            camera.target.z += forward_norm.z * self.speed;
+            direction += forward;
         }
         if self.is_backward_pressed {
-            camera.eye.x -= forward_norm.x * self.speed;
-            camera.eye.z -= forward_norm.z * self.speed;
-            camera.target.x -= forward_norm.x * self.speed;
-            camera.target.z -= forward_norm.z * self.speed;
+            direction -= forward;
         }
         if self.is_right_pressed {
-            camera.eye += right_norm * self.speed;
-            camera.target += right_norm * self.speed;
+            direction += right;
         }
         if self.is_left_pressed {
-            camera.eye -= right_norm * self.speed;
-            camera.target -= right_norm * self.speed;
+            direction -= right;
+        }
+        if self.edge_pan {
+            direction += right * self.edge.0 - forward * self.edge.1;
+        }
+
+        let mut goal_velocity = cgmath::Vector3::zero();
+        if direction.magnitude2() > 0.0 {
+            let mut speed = self.speed * (orbit.distance / PAN_REFERENCE_DISTANCE).max(0.5);
+            if self.is_sprint_pressed {
+                speed *= SPRINT_FACTOR;
+            }
+            goal_velocity = direction.normalize() * speed;
+        }
+        orbit.velocity += (goal_velocity - orbit.velocity) * smoothing(PAN_DAMPING, dt);
+        orbit.target += orbit.velocity * dt;
+
+        let t = smoothing(DAMPING, dt);
+        orbit.yaw += (orbit.goal_yaw - orbit.yaw) * t;
+        orbit.pitch += (orbit.goal_pitch - orbit.pitch) * t;
+        orbit.distance += (orbit.goal_distance - orbit.distance) * t;
+
+        // The target rests on top of the ground under it
+        if let Some(height) = ground(orbit.target.x.floor() as i32, orbit.target.z.floor() as i32) {
+            orbit.target.y += (height as f32 + 1.0 - orbit.target.y) * t;
         }
-        if self.is_zoom_in_pressed && camera.eye.y >= camera.target.y + 0.5 {
-            camera.eye += forward_norm * self.speed;
+
+        camera.target = orbit.target;
+        camera.eye = orbit.target + orbit.offset();
+        if let Some(height) = ground(camera.eye.x.floor() as i32, camera.eye.z.floor() as i32) {
+            camera.eye.y = camera.eye.y.max(height as f32 + 1.0 + MIN_EYE_CLEARANCE);
         }
-        if self.is_zoom_out_pressed && camera.eye.y <= 10.0 {
-            camera.eye -= forward_norm * self.speed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Camera {
+        Camera {
+            eye: (0.0, 1.0, 2.0).into(),
+            target: (0.0, 1.0, 0.0).into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+            projection: Projection::Perspective,
         }
-        if self.is_rotation_right_pressed {
-            let angle = cgmath::Deg(10.0 * self.speed);
-            let sin = cgmath::Deg::sin(angle);
-            let cos = cgmath::Deg::cos(angle);
+    }
+
+    #[test]
+    fn free_fly_clamps_pitch() {
+        let mut camera = camera();
+        let mut controller = CameraController::new(1.0);
+        controller.set_mode(CameraMode::FreeFly, &camera);
 
-            camera.eye.x -= camera.target.x;
-            camera.eye.z -= camera.target.z;
+        controller.process_mouse_motion((0.0, -1e6));
+        controller.update_camera(&mut camera, 0.1, |_, _| None);
 
-            let new_x = camera.eye.x * cos - camera.eye.z * sin;
-            let new_z = camera.eye.x * sin + camera.eye.z * cos;
+        let forward = camera.target - camera.eye;
+        assert!(forward.y > 0.0);
+        assert!((forward.y / forward.x.hypot(forward.z)).atan() <= MAX_PITCH + 1e-4);
+    }
+
+    #[test]
+    fn free_fly_starts_looking_at_the_target() {
+        let mut camera = camera();
+        let mut controller = CameraController::new(1.0);
+        controller.set_mode(CameraMode::FreeFly, &camera);
+        controller.is_forward_pressed = true;
+        controller.update_camera(&mut camera, 0.5, |_, _| None);
+
+        assert!((camera.eye.z - 1.5).abs() < 1e-5, "{:?}", camera.eye);
+        assert!((camera.target.z + 0.5).abs() < 1e-5, "{:?}", camera.target);
+    }
 
-            camera.eye.x = new_x + camera.target.x;
-            camera.eye.z = new_z + camera.target.z;
+    #[test]
+    fn orbit_zoom_stays_within_limits() {
+        let mut camera = camera();
+        let mut controller = CameraController::new(1.0);
+        controller.zoom = -100.0;
+        for _ in 0..120 {
+            controller.update_camera(&mut camera, 1.0 / 60.0, |_, _| None);
         }
-        if self.is_rotation_left_pressed {
-            let angle = -cgmath::Deg(10.0 * self.speed);
-            let sin = cgmath::Deg::sin(angle);
-            let cos = cgmath::Deg::cos(angle);
 
-            camera.eye.x -= camera.target.x;
-            camera.eye.z -= camera.target.z;
+        let distance = cgmath::InnerSpace::magnitude(camera.eye - camera.target);
+        assert!((distance - MAX_DISTANCE).abs() < 0.1, "{}", distance);
+    }
+
+    #[test]
+    fn orbit_follows_the_ground() {
+        let mut camera = camera();
+        let mut controller = CameraController::new(1.0);
+        for _ in 0..120 {
+            controller.update_camera(&mut camera, 1.0 / 60.0, |x, _| Some(x.max(5)));
+        }
 
-            let new_x = camera.eye.x * cos - camera.eye.z * sin;
-            let new_z = camera.eye.x * sin + camera.eye.z * cos;
+        assert!((camera.target.y - 6.0).abs() < 0.01, "{:?}", camera.target);
+        assert!(camera.eye.y >= 7.5, "{:?}", camera.eye);
+    }
 
-            camera.eye.x = new_x + camera.target.x;
-            camera.eye.z = new_z + camera.target.z;
+    #[test]
+    fn isometric_turns_in_quarters() {
+        let mut camera = camera();
+        camera.projection = Projection::Isometric;
+        let mut controller = CameraController::new(1.0);
+        controller.rotation_steps = 1;
+        for _ in 0..240 {
+            controller.update_camera(&mut camera, 1.0 / 60.0, |_, _| None);
         }
-        if self.reset {
-            camera.eye.x = camera.target.x - 5.0;
-            camera.eye.z = camera.target.z;
+
+        // The eye started at +z, 90°, the nearest diagonal is 135° and a quarter turn more
+        let offset = camera.eye - camera.target;
+        let yaw = offset.z.atan2(offset.x).to_degrees();
+        assert!((yaw + 135.0).abs() < 0.1, "{}", yaw);
+        let pitch = offset.y.atan2(offset.x.hypot(offset.z));
+        assert!((pitch - ISOMETRIC_PITCH).abs() < 1e-3, "{}", pitch);
+    }
+
+    #[test]
+    fn switching_modes_drops_pending_turns() {
+        let mut camera = camera();
+        camera.projection = Projection::Isometric;
+        let mut controller = CameraController::new(1.0);
+        controller.rotation_steps = 1;
+        controller.set_mode(CameraMode::FreeFly, &camera);
+        controller.set_mode(CameraMode::Orbit, &camera);
+        for _ in 0..240 {
+            controller.update_camera(&mut camera, 1.0 / 60.0, |_, _| None);
         }
+
+        let offset = camera.eye - camera.target;
+        let yaw = offset.z.atan2(offset.x).to_degrees();
+        assert!((yaw - 135.0).abs() < 0.1, "{}", yaw);
+    }
+
+    #[test]
+    fn orthographic_keeps_depth_out_of_the_size() {
+        let mut camera = camera();
+        camera.projection = Projection::Orthographic;
+        let view_projection = camera.build_view_projection_matrix();
+
+        let near = view_projection * cgmath::vec4(1.0, 1.0, 1.0, 1.0);
+        let far = view_projection * cgmath::vec4(1.0, 1.0, -5.0, 1.0);
+        assert!((near.x / near.w - far.x / far.w).abs() < 1e-5);
+        assert!((near.z / near.w) < (far.z / far.w));
     }
 }
@@ -0,0 +1,160 @@
+// Screenshots and image sequences of the rendered frames, e.g. for timelapses
+// of a growing colony.
+
+pub struct Capture {
+    directory: std::path::PathBuf,
+    screenshot_requested: bool,
+    interval: std::time::Duration,
+    // Directory, next frame number and the time it is due
+    recording: Option<(std::path::PathBuf, u32, std::time::Instant)>,
+    // Frames still being read back and where they go
+    pending: Vec<(Vec<std::path::PathBuf>, super::wgpu::PendingImage)>,
+}
+
+impl Capture {
+    pub fn new(directory: std::path::PathBuf, interval: std::time::Duration) -> Self {
+        Self {
+            directory,
+            screenshot_requested: false,
+            interval,
+            recording: None,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn request_screenshot(&mut self) {
+        self.screenshot_requested = true;
+    }
+
+    pub fn toggle_recording(&mut self) {
+        match self.recording.take() {
+            Some((directory, frames, _)) => {
+                log::info!("Recorded {} frames to {}", frames, directory.display())
+            }
+            None => {
+                let directory = self.directory.join(format!("timelapse-{}", timestamp()));
+                log::info!(
+                    "Recording a frame every {:?} to {}",
+                    self.interval,
+                    directory.display()
+                );
+                self.recording = Some((directory, 0, std::time::Instant::now()));
+            }
+        }
+    }
+
+    /// Whether the next rendered frame should be read back.
+    pub fn wants_frame(&self) -> bool {
+        self.screenshot_requested
+            || matches!(self.recording, Some((_, _, due)) if due <= std::time::Instant::now())
+    }
+
+    /// Saves the frame once it was read back, see `update`.
+    pub fn save(&mut self, frame: super::wgpu::PendingImage) {
+        let mut paths = Vec::new();
+
+        if self.screenshot_requested {
+            self.screenshot_requested = false;
+            paths.push(
+                self.directory
+                    .join(format!("screenshot-{}.png", timestamp())),
+            );
+        }
+
+        if let Some((directory, frame, due)) = &mut self.recording {
+            let now = std::time::Instant::now();
+            if *due <= now {
+                paths.push(directory.join(format!("frame-{:05}.png", frame)));
+                *frame += 1;
+                // Skip intervals missed while the window was busy
+                while *due <= now {
+                    *due += self.interval;
+                }
+            }
+        }
+
+        if !paths.is_empty() {
+            self.pending.push((paths, frame));
+        }
+    }
+
+    /// Saves the frames read back by now on a background thread, so neither
+    /// the readback nor encoding stall rendering.
+    pub fn update(&mut self, device: &wgpu::Device) {
+        if self.pending.is_empty() {
+            return;
+        }
+        device.poll(wgpu::Maintain::Poll);
+
+        self.pending.retain(|(paths, frame)| {
+            let image = match frame.try_read() {
+                None => return true,
+                Some(Ok(image)) => image,
+                Some(Err(e)) => {
+                    log::error!("Could not read back a frame: {}", e);
+                    return false;
+                }
+            };
+
+            let paths = paths.clone();
+            std::thread::spawn(move || {
+                for path in paths {
+                    match image.save_png(&path) {
+                        Ok(()) => log::info!("Saved {}", path.display()),
+                        Err(e) => log::error!("Could not save {}: {}", path.display(), e),
+                    }
+                }
+            });
+            false
+        });
+    }
+}
+
+// UTC time as 20240131-235959-123, sorting like the capture order
+fn timestamp() -> String {
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = time.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        year,
+        month,
+        day,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        time.subsec_millis()
+    )
+}
+
+// Days since 1970-01-01 to a proleptic Gregorian date,
+// see http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_days_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19753), (2024, 1, 31));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+}
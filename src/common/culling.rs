@@ -0,0 +1,623 @@
+// GPU driven culling: a compute pass tests the bounds of every draw slot
+// against the frustum and a depth pyramid of the previous frame, and writes the
+// arguments the bundles' indirect draws read. Needs compute shaders and
+// indirect execution, which the GL fallback adapter has.
+
+use wgpu::util::DeviceExt;
+
+// Bytes of the arguments of one `draw_indexed_indirect`
+pub const INDIRECT_SIZE: wgpu::BufferAddress = 20;
+
+const INITIAL_CAPACITY: usize = 64;
+// Dynamic uniform offsets are aligned to 256 bytes
+const LEVEL_STRIDE: wgpu::BufferAddress = 256;
+const WORKGROUP_SIZE: u32 = 64;
+const HI_Z_WORKGROUP_SIZE: u32 = 8;
+
+/// Whether the adapter can run the culling pass.
+pub fn is_supported(flags: wgpu::DownlevelFlags) -> bool {
+    flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::INDIRECT_EXECUTION)
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawRaw {
+    min: [f32; 3],
+    index_count: u32,
+    max: [f32; 3],
+    instance_count: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+    previous_view_proj: [[f32; 4]; 4],
+    planes: [[f32; 4]; 6],
+    draw_count: u32,
+    hi_z_levels: u32,
+    hi_z_size: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LevelUniform {
+    source_offset: u32,
+    offset: u32,
+    source_size: [u32; 2],
+    size: [u32; 2],
+    _padding: [u32; 2],
+}
+
+// Where the copy of the indirect arguments read back for the frame stats is
+enum Readback {
+    Idle,
+    // Copied by the last cull pass, mapped once that frame is submitted
+    Copied,
+    Mapping(std::sync::mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>),
+}
+
+// Farthest depth pyramid, level 0 is the size of the depth buffer
+struct HiZ {
+    size: (u32, u32),
+    level_count: u32,
+    buffer: wgpu::Buffer,
+    // One level description per dynamic offset
+    levels_buffer: wgpu::Buffer,
+    // The view projection it was built with, none until the first build
+    view_proj: Option<cgmath::Matrix4<f32>>,
+}
+
+impl HiZ {
+    fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let level_count = 32 - width.max(height).leading_zeros();
+        let level_size = |level: u32| ((width >> level).max(1), (height >> level).max(1));
+
+        let mut offset = 0;
+        let mut levels = Vec::new();
+        for level in 0..level_count {
+            let source_size = level_size(level.saturating_sub(1));
+            let size = level_size(level);
+            let source_offset = offset
+                - if level > 0 {
+                    source_size.0 * source_size.1
+                } else {
+                    0
+                };
+            levels.extend_from_slice(bytemuck::bytes_of(&LevelUniform {
+                source_offset,
+                offset,
+                source_size: [source_size.0, source_size.1],
+                size: [size.0, size.1],
+                _padding: [0; 2],
+            }));
+            levels.resize(levels.len().next_multiple_of(LEVEL_STRIDE as usize), 0);
+            offset += size.0 * size.1;
+        }
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Buffer - Hi-Z"),
+            size: offset as wgpu::BufferAddress * 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let levels_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Buffer - Hi-Z Levels"),
+            contents: &levels,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        Self {
+            size: (width, height),
+            level_count,
+            buffer,
+            levels_buffer,
+            view_proj: None,
+        }
+    }
+}
+
+pub struct GpuCulling {
+    cull_pipeline: wgpu::ComputePipeline,
+    copy_pipeline: wgpu::ComputePipeline,
+    downsample_pipeline: wgpu::ComputePipeline,
+    hi_z_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    draws_buffer: wgpu::Buffer,
+    pub indirect_buffer: wgpu::Buffer,
+    draws: Vec<DrawRaw>,
+    readback_buffer: wgpu::Buffer,
+    readback: Readback,
+    // Drawn and culled chunks of the last frame read back
+    stats: super::frustum::CullStats,
+    hi_z: HiZ,
+    // View projection of the frame being drawn
+    view_proj: cgmath::Matrix4<f32>,
+}
+
+impl GpuCulling {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let cull_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader - Cull"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../assets/shaders/cull.wgsl").into()),
+        });
+        let hi_z_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader - Hi-Z"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../assets/shaders/hi_z.wgsl").into()),
+        });
+
+        let uniform = |binding, has_dynamic_offset| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let cull_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bind Group Layout - Cull"),
+            entries: &[
+                uniform(0, false),
+                storage(1, true),
+                storage(2, false),
+                storage(3, true),
+            ],
+        });
+        let hi_z_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bind Group Layout - Hi-Z"),
+            entries: &[
+                uniform(0, true),
+                storage(1, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline = |label, layout, module, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(
+                    &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some(label),
+                        bind_group_layouts: &[layout],
+                        push_constant_ranges: &[],
+                    }),
+                ),
+                module,
+                entry_point,
+            })
+        };
+        let cull_pipeline = pipeline("Pipeline - Cull", &cull_layout, &cull_shader, "cs_main");
+        let copy_pipeline = pipeline(
+            "Pipeline - Hi-Z Copy",
+            &hi_z_layout,
+            &hi_z_shader,
+            "cs_copy",
+        );
+        let downsample_pipeline = pipeline(
+            "Pipeline - Hi-Z Downsample",
+            &hi_z_layout,
+            &hi_z_shader,
+            "cs_downsample",
+        );
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Buffer - Cull"),
+            size: std::mem::size_of::<CullUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let draws = vec![DrawRaw::default(); INITIAL_CAPACITY];
+        let (draws_buffer, indirect_buffer, readback_buffer) = Self::create_buffers(device, &draws);
+
+        Self {
+            cull_pipeline,
+            copy_pipeline,
+            downsample_pipeline,
+            hi_z_layout,
+            uniform_buffer,
+            draws_buffer,
+            indirect_buffer,
+            draws,
+            readback_buffer,
+            readback: Readback::Idle,
+            stats: super::frustum::CullStats::default(),
+            // Replaced by one the size of the depth buffer after the first frame
+            hi_z: HiZ::new(device, 1, 1),
+            view_proj: cgmath::SquareMatrix::identity(),
+        }
+    }
+
+    fn create_buffers(
+        device: &wgpu::Device,
+        draws: &[DrawRaw],
+    ) -> (wgpu::Buffer, wgpu::Buffer, wgpu::Buffer) {
+        let draws_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Buffer - Cull Draws"),
+            contents: bytemuck::cast_slice(draws),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let indirect_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Buffer - Cull Indirect"),
+            size: draws.len() as wgpu::BufferAddress * INDIRECT_SIZE,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Buffer - Cull Readback"),
+            size: indirect_buffer.size(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        (draws_buffer, indirect_buffer, readback_buffer)
+    }
+
+    /// Makes room for `slots` draws. Returns true when the indirect buffer was
+    /// replaced, so bundles drawing from the old one need to be recorded again.
+    pub fn reserve(&mut self, device: &wgpu::Device, slots: usize) -> bool {
+        if slots <= self.draws.len() {
+            return false;
+        }
+
+        self.draws
+            .resize(slots.next_power_of_two(), DrawRaw::default());
+        (
+            self.draws_buffer,
+            self.indirect_buffer,
+            self.readback_buffer,
+        ) = Self::create_buffers(device, &self.draws);
+        self.readback = Readback::Idle;
+        true
+    }
+
+    /// Sets what slot `slot` draws when the box between `min` and `max` is visible.
+    pub fn set_draw(
+        &mut self,
+        queue: &wgpu::Queue,
+        slot: usize,
+        min: cgmath::Point3<f32>,
+        max: cgmath::Point3<f32>,
+        index_count: u32,
+        instance_count: u32,
+    ) {
+        self.draws[slot] = DrawRaw {
+            min: min.into(),
+            index_count,
+            max: max.into(),
+            instance_count,
+        };
+        queue.write_buffer(
+            &self.draws_buffer,
+            slot as wgpu::BufferAddress * std::mem::size_of::<DrawRaw>() as wgpu::BufferAddress,
+            bytemuck::cast_slice(&self.draws[slot..slot + 1]),
+        );
+    }
+
+    /// Culls for the camera with `view_proj` in the next frame.
+    pub fn update(&mut self, queue: &wgpu::Queue, view_proj: cgmath::Matrix4<f32>) {
+        let frustum = super::frustum::Frustum::from_matrix(view_proj);
+        let uniform = CullUniform {
+            previous_view_proj: self.hi_z.view_proj.unwrap_or(view_proj).into(),
+            planes: frustum.planes().map(Into::into),
+            draw_count: self.draws.len() as u32,
+            hi_z_levels: match self.hi_z.view_proj {
+                Some(_) => self.hi_z.level_count,
+                None => 0,
+            },
+            hi_z_size: [self.hi_z.size.0, self.hi_z.size.1],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        self.view_proj = view_proj;
+    }
+
+    /// Writes the indirect arguments, before the render pass drawing them.
+    pub fn encode_cull(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        self.read_stats(device);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bind Group - Cull"),
+            layout: &self.cull_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.draws_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.indirect_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.hi_z.buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Compute Pass - Cull"),
+        });
+        compute_pass.set_pipeline(&self.cull_pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups((self.draws.len() as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+        drop(compute_pass);
+
+        // Only one copy is in flight, frames in between keep the older stats
+        if let Readback::Idle = self.readback {
+            encoder.copy_buffer_to_buffer(
+                &self.indirect_buffer,
+                0,
+                &self.readback_buffer,
+                0,
+                self.indirect_buffer.size(),
+            );
+            self.readback = Readback::Copied;
+        }
+    }
+
+    /// Maps the indirect arguments copied by the cull pass, after its frame was submitted.
+    pub fn map_stats(&mut self) {
+        if let Readback::Copied = self.readback {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            self.readback_buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    let _ = sender.send(result);
+                });
+            self.readback = Readback::Mapping(receiver);
+        }
+    }
+
+    // Counts the chunks of the mapped copy without waiting for it
+    fn read_stats(&mut self, device: &wgpu::Device) {
+        let receiver = match &self.readback {
+            Readback::Mapping(receiver) => receiver,
+            _ => return,
+        };
+        device.poll(wgpu::Maintain::Poll);
+        match receiver.try_recv() {
+            Err(std::sync::mpsc::TryRecvError::Empty) => return,
+            Ok(Ok(())) => {
+                let slice = self.readback_buffer.slice(..);
+                let mut stats = super::frustum::CullStats::default();
+                for (_, indirect) in self
+                    .draws
+                    .iter()
+                    .zip(bytemuck::cast_slice::<u8, [u32; 5]>(
+                        &slice.get_mapped_range(),
+                    ))
+                    .filter(|(draw, _)| draw.instance_count > 0)
+                {
+                    if indirect[1] > 0 {
+                        stats.drawn += 1;
+                    } else {
+                        stats.culled += 1;
+                    }
+                }
+                self.readback_buffer.unmap();
+                self.stats = stats;
+            }
+            Ok(Err(e)) => log::warn!("Failed to read back the culled draws: {}", e),
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => {}
+        }
+        self.readback = Readback::Idle;
+    }
+
+    /// Chunks the culling pass drew and culled, a frame or more behind.
+    pub fn stats(&self) -> super::frustum::CullStats {
+        self.stats
+    }
+
+    /// Builds the depth pyramid the next frame tests against, after the render pass.
+    pub fn encode_hi_z(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        depth_view: &wgpu::TextureView,
+        size: (u32, u32),
+    ) {
+        if self.hi_z.size != size {
+            self.hi_z = HiZ::new(device, size.0, size.1);
+        }
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bind Group - Hi-Z"),
+            layout: &self.hi_z_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &self.hi_z.levels_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<LevelUniform>() as u64),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.hi_z.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(depth_view),
+                },
+            ],
+        });
+
+        // Every dispatch reads the level the one before wrote
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Compute Pass - Hi-Z"),
+        });
+        for level in 0..self.hi_z.level_count {
+            let (width, height) = ((size.0 >> level).max(1), (size.1 >> level).max(1));
+            compute_pass.set_pipeline(match level {
+                0 => &self.copy_pipeline,
+                _ => &self.downsample_pipeline,
+            });
+            compute_pass.set_bind_group(
+                0,
+                &bind_group,
+                &[(level as wgpu::BufferAddress * LEVEL_STRIDE) as u32],
+            );
+            compute_pass.dispatch_workgroups(
+                width.div_ceil(HI_Z_WORKGROUP_SIZE),
+                height.div_ceil(HI_Z_WORKGROUP_SIZE),
+                1,
+            );
+        }
+        drop(compute_pass);
+
+        self.hi_z.view_proj = Some(self.view_proj);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::frustum::CullStats;
+    use crate::common::headless::Headless;
+    use crate::world::voxel_manager::VoxelManger;
+
+    // Instance counts the culling pass wrote for every slot
+    fn instance_counts(headless: &Headless, gpu_culling: &GpuCulling) -> Vec<u32> {
+        let device = &headless.wgpu_manager.device;
+        let size = gpu_culling.indirect_buffer.size();
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        encoder.copy_buffer_to_buffer(&gpu_culling.indirect_buffer, 0, &buffer, 0, size);
+        headless
+            .wgpu_manager
+            .queue
+            .submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv().unwrap().unwrap();
+
+        let counts = bytemuck::cast_slice::<u8, [u32; 5]>(&slice.get_mapped_range())
+            .iter()
+            .map(|draw| draw[1])
+            .collect();
+        counts
+    }
+
+    #[test]
+    fn culls_chunks_outside_the_frustum_and_behind_others() {
+        let _lock = super::super::headless::RENDER_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        let mut headless =
+            pollster::block_on(Headless::new(winit::dpi::PhysicalSize::new(160, 120)));
+        if !is_supported(headless.wgpu_manager.downlevel_flags) {
+            return;
+        }
+        headless.camera_manager.camera.eye = cgmath::point3(16.0, 16.0, -10.0);
+        headless.camera_manager.camera.target = cgmath::point3(16.0, 16.0, 40.0);
+
+        let mut voxel_manager = VoxelManger::new(
+            &headless.wgpu_manager.device,
+            &headless.wgpu_manager.queue,
+            &headless.wgpu_manager.config,
+            &headless.camera_manager.camera_bind_group_layout,
+            super::super::state::State::depth_stencil(),
+            crate::world::mesher::MeshingMode::Instanced,
+        );
+        voxel_manager.gpu_culling = Some(GpuCulling::new(&headless.wgpu_manager.device));
+
+        // A solid chunk filling the view, one voxel behind it and one behind the camera
+        let stone = voxel_manager.block_registry.get_id("stone").unwrap();
+        for x in 0..32 {
+            for y in 0..32 {
+                for z in 0..32 {
+                    voxel_manager.set_voxel(cgmath::vec3(x, y, z), stone);
+                }
+            }
+        }
+        voxel_manager.set_voxel(cgmath::vec3(16, 16, 80), stone);
+        voxel_manager.set_voxel(cgmath::vec3(16, 16, -50), stone);
+
+        let mut voxel_manager = voxel_manager
+            .update_map()
+            .update_buffers(&headless.wgpu_manager.device, &headless.wgpu_manager.queue);
+        voxel_manager.finish_bundle(
+            &mut headless.bundle_manager,
+            &headless.wgpu_manager.device,
+            &headless.wgpu_manager.queue,
+            &headless.wgpu_manager.config,
+            &headless.camera_manager.camera_bind_group,
+            super::super::state::State::bundle_depth_stencil(),
+        );
+        let slot = |z| voxel_manager.chunks[&cgmath::vec3(0, 0, z)].bundle.unwrap();
+        let (wall, hidden, behind) = (slot(0), slot(2), slot(-2));
+
+        // Without a depth pyramid only the frustum culls
+        headless.render(&mut voxel_manager);
+        let counts = instance_counts(&headless, voxel_manager.gpu_culling.as_ref().unwrap());
+        assert!(counts[wall] > 0);
+        assert!(counts[hidden] > 0);
+        assert_eq!(counts[behind], 0);
+
+        headless.render(&mut voxel_manager);
+        let counts = instance_counts(&headless, voxel_manager.gpu_culling.as_ref().unwrap());
+        assert!(counts[wall] > 0);
+        assert_eq!(counts[hidden], 0);
+        assert_eq!(counts[behind], 0);
+
+        // The stats are read back a frame late
+        let stats =
+            |voxel_manager: &VoxelManger| voxel_manager.gpu_culling.as_ref().unwrap().stats();
+        assert_eq!(
+            stats(&voxel_manager),
+            CullStats {
+                drawn: 2,
+                culled: 1
+            }
+        );
+        headless.render(&mut voxel_manager);
+        assert_eq!(
+            stats(&voxel_manager),
+            CullStats {
+                drawn: 1,
+                culled: 2
+            }
+        );
+    }
+}
@@ -0,0 +1,92 @@
+// View frustum planes for culling on the CPU.
+
+#[derive(Copy, Clone, Debug)]
+pub struct Frustum {
+    // Normal in xyz and distance in w, inside where the dot product with (p, 1) >= 0
+    planes: [cgmath::Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix with wgpu's 0..1 clip depth.
+    pub fn from_matrix(matrix: cgmath::Matrix4<f32>) -> Self {
+        use cgmath::InnerSpace;
+        use cgmath::Matrix;
+
+        let row = |i| matrix.row(i);
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ]
+        .map(|plane| plane / plane.truncate().magnitude());
+
+        Self { planes }
+    }
+
+    pub fn planes(&self) -> [cgmath::Vector4<f32>; 6] {
+        self.planes
+    }
+
+    /// Whether any part of the box between `min` and `max` may be visible. Boxes
+    /// near the corners can pass without being inside, which only costs a draw.
+    pub fn intersects_aabb(&self, min: cgmath::Point3<f32>, max: cgmath::Point3<f32>) -> bool {
+        use cgmath::InnerSpace;
+
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the normal
+            let corner = cgmath::vec3(
+                if plane.x >= 0.0 { max.x } else { min.x },
+                if plane.y >= 0.0 { max.y } else { min.y },
+                if plane.z >= 0.0 { max.z } else { min.z },
+            );
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+/// Chunks submitted and skipped in a frame.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CullStats {
+    pub drawn: usize,
+    pub culled: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frustum() -> Frustum {
+        let camera = super::super::camera::Camera {
+            eye: (0.0, 0.0, 0.0).into(),
+            target: (0.0, 0.0, -1.0).into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: 1.0,
+            fovy: 90.0,
+            znear: 0.1,
+            zfar: 100.0,
+            projection: super::super::camera::Projection::Perspective,
+        };
+        Frustum::from_matrix(camera.build_view_projection_matrix())
+    }
+
+    #[test]
+    fn keeps_boxes_in_view() {
+        let frustum = frustum();
+        assert!(frustum.intersects_aabb((-1.0, -1.0, -6.0).into(), (1.0, 1.0, -4.0).into()));
+        // Straddling the left plane
+        assert!(frustum.intersects_aabb((-12.0, 0.0, -6.0).into(), (-5.0, 1.0, -5.0).into()));
+    }
+
+    #[test]
+    fn culls_boxes_outside() {
+        let frustum = frustum();
+        // Behind, left of, above and past the far plane
+        assert!(!frustum.intersects_aabb((-1.0, -1.0, 1.0).into(), (1.0, 1.0, 3.0).into()));
+        assert!(!frustum.intersects_aabb((-20.0, 0.0, -6.0).into(), (-10.0, 1.0, -5.0).into()));
+        assert!(!frustum.intersects_aabb((0.0, 10.0, -6.0).into(), (1.0, 12.0, -5.0).into()));
+        assert!(!frustum.intersects_aabb((0.0, 0.0, -120.0).into(), (1.0, 1.0, -110.0).into()));
+    }
+}
@@ -0,0 +1,262 @@
+// Golden image tests: canonical scenes are rendered headlessly and compared
+// against the reference PNGs in assets/golden.
+//
+// Run with `UPDATE_GOLDEN=1 cargo test` to rewrite the references after an
+// intended change to the renderer. Failing scenes write the rendered frame and
+// a diff image to target/golden.
+
+use super::headless::Headless;
+use super::image::Image;
+use crate::world::mesher::MeshingMode;
+use crate::world::voxel_manager::VoxelManger;
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 120;
+
+// Maximum difference of a channel before a pixel counts as mismatched
+const TOLERANCE: u8 = 8;
+// Software rasterizers may disagree on a few edge pixels
+const MAX_MISMATCHED: f32 = 0.002;
+
+fn new_image(width: u32, height: u32) -> Image {
+    Image {
+        width,
+        height,
+        pixels: vec![0; (width * height * 4) as usize],
+    }
+}
+
+fn pixel(image: &Image, x: u32, y: u32) -> [u8; 4] {
+    let i = ((x + y * image.width) * 4) as usize;
+    image.pixels[i..i + 4].try_into().unwrap()
+}
+
+fn set_pixel(image: &mut Image, x: u32, y: u32, color: [u8; 4]) {
+    let i = ((x + y * image.width) * 4) as usize;
+    image.pixels[i..i + 4].copy_from_slice(&color);
+}
+
+pub struct Comparison {
+    pub mismatched: usize,
+    pub max_difference: u8,
+    pub diff: Image,
+}
+
+/// Compares the images pixel by pixel. Mismatched pixels are red in the diff
+/// image, matching ones a faded copy of the expected image.
+pub fn compare(actual: &Image, expected: &Image, tolerance: u8) -> Comparison {
+    assert_eq!(
+        (actual.width, actual.height),
+        (expected.width, expected.height),
+        "Image sizes differ"
+    );
+
+    let mut diff = new_image(actual.width, actual.height);
+    let mut mismatched = 0;
+    let mut max_difference = 0;
+
+    for y in 0..actual.height {
+        for x in 0..actual.width {
+            let a = pixel(actual, x, y);
+            let e = pixel(expected, x, y);
+            let difference = a.iter().zip(e).map(|(a, e)| a.abs_diff(e)).max().unwrap();
+            max_difference = max_difference.max(difference);
+
+            if difference > tolerance {
+                mismatched += 1;
+                set_pixel(&mut diff, x, y, [255, 0, 0, 255]);
+            } else {
+                set_pixel(&mut diff, x, y, [e[0] / 4, e[1] / 4, e[2] / 4, 255]);
+            }
+        }
+    }
+
+    Comparison {
+        mismatched,
+        max_difference,
+        diff,
+    }
+}
+
+fn render_scene<F>(
+    meshing_mode: MeshingMode,
+    eye: cgmath::Point3<f32>,
+    target: cgmath::Point3<f32>,
+    build: F,
+) -> Image
+where
+    F: FnOnce(&mut VoxelManger),
+{
+    let _lock = super::headless::RENDER_LOCK
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    let mut headless =
+        pollster::block_on(Headless::new(winit::dpi::PhysicalSize::new(WIDTH, HEIGHT)));
+    headless.camera_manager.camera.eye = eye;
+    headless.camera_manager.camera.target = target;
+
+    let mut voxel_manager = VoxelManger::new(
+        &headless.wgpu_manager.device,
+        &headless.wgpu_manager.queue,
+        &headless.wgpu_manager.config,
+        &headless.camera_manager.camera_bind_group_layout,
+        super::state::State::depth_stencil(),
+        meshing_mode,
+    );
+    if super::culling::is_supported(headless.wgpu_manager.downlevel_flags) {
+        voxel_manager.gpu_culling = Some(super::culling::GpuCulling::new(
+            &headless.wgpu_manager.device,
+        ));
+    }
+    build(&mut voxel_manager);
+
+    let mut voxel_manager = voxel_manager
+        .update_map()
+        .update_buffers(&headless.wgpu_manager.device, &headless.wgpu_manager.queue);
+    voxel_manager.finish_bundle(
+        &mut headless.bundle_manager,
+        &headless.wgpu_manager.device,
+        &headless.wgpu_manager.queue,
+        &headless.wgpu_manager.config,
+        &headless.camera_manager.camera_bind_group,
+        super::state::State::bundle_depth_stencil(),
+    );
+
+    // The second frame also culls against the depth of the first
+    headless.render(&mut voxel_manager);
+    headless.render(&mut voxel_manager)
+}
+
+fn assert_golden(name: &str, actual: &Image) {
+    let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+    let reference = root.join("assets/golden").join(format!("{}.png", name));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        actual.save_png(&reference).unwrap();
+        return;
+    }
+
+    let output = root.join("target/golden");
+    let expected = match std::fs::File::open(&reference)
+        .and_then(|file| Image::read_png(std::io::BufReader::new(file)))
+    {
+        Ok(expected) => expected,
+        Err(e) => {
+            let path = output.join(format!("{}-actual.png", name));
+            actual.save_png(&path).unwrap();
+            panic!(
+                "No reference for {} ({}), the rendered frame is in {}",
+                name,
+                e,
+                path.display()
+            );
+        }
+    };
+
+    let comparison = compare(actual, &expected, TOLERANCE);
+    let allowed = ((actual.width * actual.height) as f32 * MAX_MISMATCHED) as usize;
+    if comparison.mismatched > allowed {
+        actual
+            .save_png(&output.join(format!("{}-actual.png", name)))
+            .unwrap();
+        comparison
+            .diff
+            .save_png(&output.join(format!("{}-diff.png", name)))
+            .unwrap();
+        panic!(
+            "{}: {} pixels differ by up to {}, see {}",
+            name,
+            comparison.mismatched,
+            comparison.max_difference,
+            output.display()
+        );
+    }
+}
+
+fn block(voxel_manager: &VoxelManger, name: &str) -> crate::world::block::BlockId {
+    voxel_manager.block_registry.get_id(name).unwrap()
+}
+
+#[test]
+fn single_cube() {
+    let image = render_scene(
+        MeshingMode::Instanced,
+        cgmath::point3(2.5, 2.0, 3.0),
+        cgmath::point3(0.5, 0.5, 0.5),
+        |voxel_manager| {
+            let stone = block(voxel_manager, "stone");
+            voxel_manager.set_voxel(cgmath::vec3(0, 0, 0), stone);
+        },
+    );
+    assert_golden("single_cube", &image);
+}
+
+#[test]
+fn plane() {
+    let image = render_scene(
+        MeshingMode::Instanced,
+        cgmath::point3(4.0, 7.0, 13.0),
+        cgmath::point3(4.0, 0.0, 4.0),
+        |voxel_manager| {
+            let grass = block(voxel_manager, "grass");
+            for x in 0..8 {
+                for z in 0..8 {
+                    voxel_manager.set_voxel(cgmath::vec3(x, 0, z), grass);
+                }
+            }
+        },
+    );
+    assert_golden("plane", &image);
+}
+
+fn stepped_terrain(voxel_manager: &mut VoxelManger) {
+    let grass = block(voxel_manager, "grass");
+    let dirt = block(voxel_manager, "dirt");
+    for x in 0..8 {
+        for z in 0..8 {
+            let height = (x + z) / 3;
+            for y in 0..height {
+                voxel_manager.set_voxel(cgmath::vec3(x, y, z), dirt);
+            }
+            voxel_manager.set_voxel(cgmath::vec3(x, height, z), grass);
+        }
+    }
+}
+
+#[test]
+fn stepped() {
+    let image = render_scene(
+        MeshingMode::Instanced,
+        cgmath::point3(-5.0, 11.0, 15.0),
+        cgmath::point3(4.0, 2.0, 4.0),
+        stepped_terrain,
+    );
+    assert_golden("stepped", &image);
+}
+
+#[test]
+fn stepped_greedy() {
+    let image = render_scene(
+        MeshingMode::Greedy,
+        cgmath::point3(-5.0, 11.0, 15.0),
+        cgmath::point3(4.0, 2.0, 4.0),
+        stepped_terrain,
+    );
+    assert_golden("stepped_greedy", &image);
+}
+
+#[test]
+fn compare_marks_differences() {
+    let mut expected = new_image(4, 2);
+    let mut actual = new_image(4, 2);
+    set_pixel(&mut expected, 1, 1, [100, 100, 100, 255]);
+    set_pixel(&mut actual, 1, 1, [104, 100, 100, 255]);
+    set_pixel(&mut actual, 3, 0, [0, 0, 50, 0]);
+
+    let comparison = compare(&actual, &expected, TOLERANCE);
+    assert_eq!(comparison.mismatched, 1);
+    assert_eq!(comparison.max_difference, 50);
+    assert_eq!(pixel(&comparison.diff, 3, 0), [255, 0, 0, 255]);
+    assert_eq!(pixel(&comparison.diff, 1, 1), [25, 25, 25, 255]);
+}
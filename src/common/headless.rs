@@ -0,0 +1,64 @@
+// Rendering without a window or surface, for screenshots from the command line
+// and rendering on CI through the software fallback adapter.
+
+// Adapters don't like being created from many test threads at once
+#[cfg(test)]
+pub(crate) static RENDER_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+pub struct Headless {
+    pub wgpu_manager: super::wgpu::WgpuManager,
+    pub camera_manager: super::camera::CameraManager,
+    pub bundle_manager: super::bundles::BundleManager,
+}
+
+impl Headless {
+    pub async fn new(size: winit::dpi::PhysicalSize<u32>) -> Self {
+        let wgpu_manager = super::wgpu::WgpuManager::new_headless(size).await;
+
+        let camera_manager =
+            super::camera::CameraManager::new(&wgpu_manager.device, &wgpu_manager.config);
+
+        let bundle_manager =
+            super::bundles::BundleManager::new(&wgpu_manager.device, &wgpu_manager.config);
+
+        Self {
+            wgpu_manager,
+            camera_manager,
+            bundle_manager,
+        }
+    }
+
+    /// Renders the chunks of `voxel_manager` the current camera sees.
+    pub fn render(
+        &mut self,
+        voxel_manager: &mut crate::world::voxel_manager::VoxelManger,
+    ) -> super::image::Image {
+        self.camera_manager
+            .update_uniform(&self.wgpu_manager.queue, 1.0);
+        if let Some(gpu_culling) = &mut voxel_manager.gpu_culling {
+            gpu_culling.update(&self.wgpu_manager.queue, self.camera_manager.view_proj);
+        }
+        let (visible, _) = voxel_manager.visible_bundles(&self.camera_manager.frustum);
+        self.wgpu_manager.render_to_image(
+            &self.bundle_manager.get_visible_bundles(&visible),
+            self.bundle_manager.get_depth_texture_view(),
+            voxel_manager.gpu_culling.as_mut(),
+        )
+    }
+}
+
+/// Renders the world picked by the command line arguments into `path`.
+pub async fn screenshot(path: &std::path::Path) -> std::io::Result<()> {
+    let size = super::window::size_arg().unwrap_or(super::window::WindowConfig::default().size);
+
+    let mut headless = Headless::new(size).await;
+    let (mut voxel_manager, _) = super::state::State::create_world(
+        &headless.wgpu_manager,
+        &mut headless.camera_manager,
+        &mut headless.bundle_manager,
+    );
+
+    headless.render(&mut voxel_manager).save_png(path)?;
+    log::info!("Saved screenshot to {}", path.display());
+    Ok(())
+}
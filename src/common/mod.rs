@@ -1,6 +1,15 @@
+pub mod args;
 pub mod bundles;
 pub mod camera;
+pub mod capture;
+pub mod culling;
+pub mod frustum;
+#[cfg(test)]
+mod golden;
+pub mod headless;
+pub mod image;
 pub mod state;
 pub mod texture;
+pub mod timing;
 pub mod wgpu;
 pub mod window;
@@ -3,53 +3,229 @@ pub struct State {
     pub wgpu_manager: super::wgpu::WgpuManager,
     pub camera_manager: super::camera::CameraManager,
     pub bundle_manager: super::bundles::BundleManager,
+    pub voxel_manager: crate::world::voxel_manager::VoxelManger,
+    pub world_path: std::path::PathBuf,
+    pub capture: super::capture::Capture,
+    pub timestep: super::timing::FixedTimestep,
+    pub frame_stats: super::timing::FrameStats,
 }
 
 impl State {
     pub async fn new() -> Self {
-        let window_manager = super::window::WindowManager::new();
+        let window_manager =
+            super::window::WindowManager::new(&super::window::WindowConfig::from_args());
 
         let wgpu_manager = super::wgpu::WgpuManager::new(&window_manager.window).await;
 
-        let camera_manager =
+        let mut camera_manager =
             super::camera::CameraManager::new(&wgpu_manager.device, &wgpu_manager.config);
 
         let mut bundle_manager =
             super::bundles::BundleManager::new(&wgpu_manager.device, &wgpu_manager.config);
 
-        crate::world::voxel_manager::VoxelManger::new(
-            &wgpu_manager.device,
-            &wgpu_manager.config,
-            &camera_manager.camera_bind_group_layout,
-            Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            8196,
-        )
-        .update_map()
-        .update_buffers(&wgpu_manager.device)
-        .finish_bundle(
-            &mut bundle_manager,
-            &wgpu_manager.device,
-            &wgpu_manager.config,
-            &camera_manager.camera_bind_group,
-            Some(wgpu::RenderBundleDepthStencil {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_read_only: false,
-                stencil_read_only: false,
-            }),
+        let (voxel_manager, world_path) =
+            Self::create_world(&wgpu_manager, &mut camera_manager, &mut bundle_manager);
+
+        // `--record` starts an image sequence right away, one frame every
+        // `--record-interval` seconds
+        let interval = super::args::arg_value("--record-interval")
+            .and_then(|seconds| seconds.parse::<f32>().ok())
+            .filter(|seconds| *seconds > 0.0)
+            .unwrap_or(1.0);
+        let mut capture = super::capture::Capture::new(
+            std::path::PathBuf::from("screenshots"),
+            std::time::Duration::from_secs_f32(interval),
         );
+        if super::args::has_flag("--record") {
+            capture.toggle_recording();
+        }
+
+        // Simulation ticks per second, independent of the frame rate
+        let tick_rate = super::args::arg_value("--tick-rate")
+            .and_then(|rate| rate.parse::<f32>().ok())
+            .filter(|rate| *rate > 0.0)
+            .unwrap_or(60.0);
+        let timestep = super::timing::FixedTimestep::new(tick_rate);
+        let frame_stats = super::timing::FrameStats::new(std::time::Duration::from_secs(5));
 
         Self {
             window_manager,
             wgpu_manager,
             bundle_manager,
             camera_manager,
+            voxel_manager,
+            world_path,
+            capture,
+            timestep,
+            frame_stats,
+        }
+    }
+
+    /// Generates or loads the world from the command line arguments and
+    /// records its bundles.
+    pub fn create_world(
+        wgpu_manager: &super::wgpu::WgpuManager,
+        camera_manager: &mut super::camera::CameraManager,
+        bundle_manager: &mut super::bundles::BundleManager,
+    ) -> (crate::world::voxel_manager::VoxelManger, std::path::PathBuf) {
+        let seed = super::args::arg_value("--seed")
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(|| {
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |time| time.as_secs())
+            });
+        log::info!("World seed: {}", seed);
+
+        let mut terrain_config = crate::world::terrain::TerrainConfig::new(seed);
+        if super::args::has_flag("--value-noise") {
+            terrain_config.noise = crate::world::terrain::noise::NoiseKind::Value;
+        }
+        let terrain_generator = crate::world::terrain::TerrainGenerator::new(terrain_config);
+
+        let world_arg = super::args::arg_value("--world");
+        let world_path = std::path::PathBuf::from(world_arg.as_deref().unwrap_or("saves/world"));
+
+        let mut voxel_manager = crate::world::voxel_manager::VoxelManger::new(
+            &wgpu_manager.device,
+            &wgpu_manager.queue,
+            &wgpu_manager.config,
+            &camera_manager.camera_bind_group_layout,
+            Self::depth_stencil(),
+            if super::args::has_flag("--greedy") {
+                crate::world::mesher::MeshingMode::Greedy
+            } else {
+                crate::world::mesher::MeshingMode::Instanced
+            },
+        );
+
+        // `--cpu-culling` keeps culling chunks on the CPU where compute works too
+        if super::culling::is_supported(wgpu_manager.downlevel_flags)
+            && !super::args::has_flag("--cpu-culling")
+        {
+            voxel_manager.gpu_culling = Some(super::culling::GpuCulling::new(&wgpu_manager.device));
+        } else {
+            log::info!("Culling chunks on the CPU");
+        }
+
+        // Only resume a colony when asked to, otherwise every launch starts a new map
+        voxel_manager = match world_arg.map(|_| voxel_manager.load(&world_path)) {
+            Some(Ok(())) => {
+                log::info!("Loaded world from {}", world_path.display());
+                voxel_manager
+            }
+            Some(Err(e)) => {
+                log::warn!("Could not load {}: {}", world_path.display(), e);
+                voxel_manager.gen_terrain(&terrain_generator)
+            }
+            None => voxel_manager.gen_terrain(&terrain_generator),
+        };
+
+        if let Some(path) = super::args::arg_value("--import") {
+            match crate::world::vox::VoxFile::open(path.as_ref()) {
+                Ok(vox) => {
+                    // Stand the scene on the ground at the origin
+                    let bottom = vox.voxels().iter().map(|(p, _)| p.y).min().unwrap_or(0);
+                    let ground = voxel_manager.height_at(0, 0).unwrap_or(0);
+                    voxel_manager.stamp_vox(
+                        &vox,
+                        cgmath::vec3(0, ground + 1 - bottom, 0),
+                        // The registry is small, so props can reuse the built in blocks
+                        if super::args::has_flag("--nearest-colors") {
+                            crate::world::vox::ColorMapping::Nearest
+                        } else {
+                            crate::world::vox::ColorMapping::Exact
+                        },
+                    );
+                    log::info!("Imported {}", path);
+                }
+                Err(e) => log::warn!("Could not import {}: {}", path, e),
+            }
+        }
+
+        if let Some(path) = super::args::arg_value("--export") {
+            // `--export-box x0,y0,z0,x1,y1,z1` picks the corners, otherwise the whole world
+            let corners = super::args::arg_value("--export-box")
+                .and_then(|corners| {
+                    let v = corners
+                        .split(',')
+                        .map(|v| v.trim().parse::<i32>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .ok()?;
+                    (v.len() == 6).then(|| {
+                        (
+                            cgmath::vec3(v[0].min(v[3]), v[1].min(v[4]), v[2].min(v[5])),
+                            cgmath::vec3(v[0].max(v[3]), v[1].max(v[4]), v[2].max(v[5])),
+                        )
+                    })
+                })
+                .or_else(|| voxel_manager.bounds());
+
+            if let Some((min, max)) = corners {
+                match voxel_manager.export_vox(min, max).save(path.as_ref()) {
+                    Ok(()) => log::info!("Exported {:?} to {:?} into {}", min, max, path),
+                    Err(e) => log::warn!("Could not export {}: {}", path, e),
+                }
+            }
         }
+
+        let mut voxel_manager = voxel_manager
+            .update_map()
+            .update_buffers(&wgpu_manager.device, &wgpu_manager.queue);
+
+        // `--export-mesh world.gltf` or `world.obj`, picked by the extension
+        if let Some(path) = super::args::arg_value("--export-mesh") {
+            let export = voxel_manager.export_mesh();
+            let path = std::path::Path::new(&path);
+            let result = match path.extension().and_then(|e| e.to_str()) {
+                Some("obj") => export.save_obj(path),
+                _ => export.save_gltf(path),
+            };
+            match result {
+                Ok(()) => log::info!(
+                    "Exported {} vertices, {} triangles to {}",
+                    export.vertex_count(),
+                    export.triangle_count(),
+                    path.display()
+                ),
+                Err(e) => log::warn!("Could not export {}: {}", path.display(), e),
+            }
+        }
+
+        voxel_manager.finish_bundle(
+            bundle_manager,
+            &wgpu_manager.device,
+            &wgpu_manager.queue,
+            &wgpu_manager.config,
+            &camera_manager.camera_bind_group,
+            Self::bundle_depth_stencil(),
+        );
+
+        // Start looking at the ground instead of from inside a hill
+        let ground = voxel_manager.height_at(0, 0).unwrap_or(0) as f32 + 1.0;
+        camera_manager.camera.eye.y += ground;
+        camera_manager.camera.target.y += ground;
+        camera_manager.previous_camera = camera_manager.camera;
+
+        (voxel_manager, world_path)
+    }
+
+    pub fn depth_stencil() -> Option<wgpu::DepthStencilState> {
+        Some(wgpu::DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        })
+    }
+
+    pub fn bundle_depth_stencil() -> Option<wgpu::RenderBundleDepthStencil> {
+        Some(wgpu::RenderBundleDepthStencil {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_read_only: false,
+            stencil_read_only: false,
+        })
     }
 
     pub fn run(mut self) {
@@ -59,53 +235,190 @@ impl State {
                 winit::event::Event::WindowEvent {
                     ref event,
                     window_id,
-                } if window_id == self.window_manager.window.id() => {
-                    if !self.wgpu_manager.input(event, &mut self.camera_manager) {
-                        match event {
-                            winit::event::WindowEvent::CloseRequested
-                            | winit::event::WindowEvent::KeyboardInput {
-                                input:
-                                    winit::event::KeyboardInput {
-                                        state: winit::event::ElementState::Pressed,
-                                        virtual_keycode: Some(winit::event::VirtualKeyCode::Escape),
-                                        ..
-                                    },
-                                ..
-                            } => *control_flow = winit::event_loop::ControlFlow::Exit,
-                            winit::event::WindowEvent::Resized(physical_size) => {
-                                self.wgpu_manager.resize(
-                                    *physical_size,
-                                    &mut self.bundle_manager,
-                                    &self.window_manager.window,
-                                );
+                } if window_id == self.window_manager.window.id()
+                    && !self.wgpu_manager.input(event, &mut self.camera_manager) =>
+                {
+                    match event {
+                        winit::event::WindowEvent::CloseRequested
+                        | winit::event::WindowEvent::KeyboardInput {
+                            input:
+                                winit::event::KeyboardInput {
+                                    state: winit::event::ElementState::Pressed,
+                                    virtual_keycode: Some(winit::event::VirtualKeyCode::Escape),
+                                    ..
+                                },
+                            ..
+                        } => *control_flow = winit::event_loop::ControlFlow::Exit,
+                        winit::event::WindowEvent::KeyboardInput {
+                            input:
+                                winit::event::KeyboardInput {
+                                    state: winit::event::ElementState::Pressed,
+                                    virtual_keycode: Some(winit::event::VirtualKeyCode::F5),
+                                    ..
+                                },
+                            ..
+                        } => match self.voxel_manager.save(&self.world_path) {
+                            Ok(()) => {
+                                log::info!("Saved world to {}", self.world_path.display())
                             }
-                            winit::event::WindowEvent::ScaleFactorChanged {
-                                new_inner_size,
-                                ..
-                            } => {
-                                self.wgpu_manager.resize(
-                                    **new_inner_size,
-                                    &mut self.bundle_manager,
-                                    &self.window_manager.window,
-                                );
+                            Err(e) => log::error!("Could not save world: {}", e),
+                        },
+                        winit::event::WindowEvent::KeyboardInput {
+                            input:
+                                winit::event::KeyboardInput {
+                                    state: winit::event::ElementState::Pressed,
+                                    virtual_keycode: Some(winit::event::VirtualKeyCode::F9),
+                                    ..
+                                },
+                            ..
+                        } => match self.voxel_manager.load(&self.world_path) {
+                            Ok(()) => {
+                                log::info!("Loaded world from {}", self.world_path.display())
                             }
-                            _ => {}
+                            Err(e) => log::error!("Could not load world: {}", e),
+                        },
+                        winit::event::WindowEvent::KeyboardInput {
+                            input:
+                                winit::event::KeyboardInput {
+                                    state: winit::event::ElementState::Pressed,
+                                    virtual_keycode: Some(winit::event::VirtualKeyCode::F12),
+                                    ..
+                                },
+                            ..
+                        } => self.capture.request_screenshot(),
+                        winit::event::WindowEvent::KeyboardInput {
+                            input:
+                                winit::event::KeyboardInput {
+                                    state: winit::event::ElementState::Pressed,
+                                    virtual_keycode: Some(winit::event::VirtualKeyCode::F10),
+                                    ..
+                                },
+                            ..
+                        } => self.capture.toggle_recording(),
+                        winit::event::WindowEvent::KeyboardInput {
+                            input:
+                                winit::event::KeyboardInput {
+                                    state: winit::event::ElementState::Pressed,
+                                    virtual_keycode: Some(winit::event::VirtualKeyCode::F11),
+                                    ..
+                                },
+                            ..
+                        } => {
+                            self.window_manager.mode = self.window_manager.mode.toggled();
+                            super::window::set_mode(
+                                &self.window_manager.window,
+                                self.window_manager.mode,
+                            );
+                        }
+                        winit::event::WindowEvent::KeyboardInput {
+                            input:
+                                winit::event::KeyboardInput {
+                                    state: winit::event::ElementState::Pressed,
+                                    virtual_keycode: Some(winit::event::VirtualKeyCode::Tab),
+                                    ..
+                                },
+                            ..
+                        } => {
+                            let camera_controller = &mut self.camera_manager.camera_controller;
+                            let mode = camera_controller.mode.toggled();
+                            camera_controller.set_mode(mode, &self.camera_manager.camera);
+                            super::window::grab_cursor(
+                                &self.window_manager.window,
+                                mode == super::camera::CameraMode::FreeFly,
+                            );
+                        }
+                        winit::event::WindowEvent::KeyboardInput {
+                            input:
+                                winit::event::KeyboardInput {
+                                    state: winit::event::ElementState::Pressed,
+                                    virtual_keycode: Some(winit::event::VirtualKeyCode::P),
+                                    ..
+                                },
+                            ..
+                        } => {
+                            let camera = &mut self.camera_manager.camera;
+                            camera.projection = camera.projection.next();
+                            log::info!("{:?} projection", camera.projection);
+                        }
+                        // Alt-tabbing away shouldn't keep the cursor captured
+                        winit::event::WindowEvent::Focused(focused)
+                            if self.camera_manager.camera_controller.mode
+                                == super::camera::CameraMode::FreeFly =>
+                        {
+                            super::window::grab_cursor(&self.window_manager.window, *focused);
                         }
+                        winit::event::WindowEvent::Resized(physical_size) => {
+                            self.wgpu_manager.resize(
+                                *physical_size,
+                                &mut self.bundle_manager,
+                                &mut self.camera_manager,
+                                &self.window_manager.window,
+                            );
+                        }
+                        winit::event::WindowEvent::ScaleFactorChanged {
+                            new_inner_size, ..
+                        } => {
+                            self.wgpu_manager.resize(
+                                **new_inner_size,
+                                &mut self.bundle_manager,
+                                &mut self.camera_manager,
+                                &self.window_manager.window,
+                            );
+                        }
+                        _ => {}
                     }
                 }
+                winit::event::Event::DeviceEvent {
+                    event: winit::event::DeviceEvent::MouseMotion { delta },
+                    ..
+                } => self
+                    .camera_manager
+                    .camera_controller
+                    .process_mouse_motion(delta),
                 winit::event::Event::RedrawRequested(window_id)
                     if window_id == self.window_manager.window.id() =>
                 {
-                    self.wgpu_manager.update(&mut self.camera_manager);
+                    self.frame_stats.frame();
+
+                    let (ticks, alpha) = self.timestep.advance();
+                    // Simulation runs at the fixed tick rate, systems get the tick length as dt
+                    for _ in 0..ticks {
+                        self.camera_manager.update(self.timestep.dt(), |x, z| {
+                            self.voxel_manager.height_at(x, z)
+                        });
+                    }
+
+                    self.wgpu_manager.update(&mut self.camera_manager, alpha);
+                    self.voxel_manager.update_dirty(
+                        &mut self.bundle_manager,
+                        &self.wgpu_manager.device,
+                        &self.wgpu_manager.queue,
+                        &self.wgpu_manager.config,
+                        &self.camera_manager.camera_bind_group,
+                        Self::bundle_depth_stencil(),
+                    );
+                    if let Some(gpu_culling) = &mut self.voxel_manager.gpu_culling {
+                        gpu_culling.update(&self.wgpu_manager.queue, self.camera_manager.view_proj);
+                    }
+                    let (visible, chunks) = self
+                        .voxel_manager
+                        .visible_bundles(&self.camera_manager.frustum);
+                    self.frame_stats.chunks(chunks);
+
+                    self.capture.update(&self.wgpu_manager.device);
                     match self.wgpu_manager.render(
-                        self.bundle_manager.get_bundles(),
+                        &self.bundle_manager.get_visible_bundles(&visible),
                         self.bundle_manager.get_depth_texture_view(),
+                        self.voxel_manager.gpu_culling.as_mut(),
+                        self.capture.wants_frame(),
                     ) {
-                        Ok(_) => {}
+                        Ok(Some(frame)) => self.capture.save(frame),
+                        Ok(None) => {}
                         // Reconfigure the surface if lost
                         Err(wgpu::SurfaceError::Lost) => self.wgpu_manager.resize(
                             self.wgpu_manager.size,
                             &mut self.bundle_manager,
+                            &mut self.camera_manager,
                             &self.window_manager.window,
                         ),
                         // The system is out of memory, we should probably quit
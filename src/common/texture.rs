@@ -1,7 +1,6 @@
+// The view keeps its texture alive, so it is all that needs to be held on to
 pub struct Texture {
-    pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
 }
 
 impl Texture {
@@ -31,23 +30,117 @@ impl Texture {
         let texture = device.create_texture(&desc);
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            compare: Some(wgpu::CompareFunction::LessEqual),
-            lod_min_clamp: -100.0,
-            lod_max_clamp: 100.0,
+
+        Self { view }
+    }
+
+    /// Uploads equally sized images as the layers of a texture array, with
+    /// mipmaps averaged on the CPU.
+    pub fn create_array(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[super::image::Image],
+        label: &str,
+    ) -> Self {
+        let (width, height) = (images[0].width, images[0].height);
+        let mip_level_count = 32 - width.max(height).leading_zeros();
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: images.len() as u32,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // Block textures multiply colors, so they are kept linear
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+
+        for (layer, image) in images.iter().enumerate() {
+            assert_eq!((image.width, image.height), (width, height));
+
+            let mut level = image.clone();
+            for mip_level in 0..mip_level_count {
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        texture: &texture,
+                        mip_level,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer as u32,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    &level.pixels,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: std::num::NonZeroU32::new(level.width * 4),
+                        rows_per_image: std::num::NonZeroU32::new(level.height),
+                    },
+                    wgpu::Extent3d {
+                        width: level.width,
+                        height: level.height,
+                        depth_or_array_layers: 1,
+                    },
+                );
+                level = downsample(&level);
+            }
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
             ..Default::default()
         });
 
-        Self {
-            texture,
-            view,
-            sampler,
+        Self { view }
+    }
+}
+
+// Next mip level, every pixel the average of the 2x2 it covers
+fn downsample(image: &super::image::Image) -> super::image::Image {
+    let (width, height) = ((image.width / 2).max(1), (image.height / 2).max(1));
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+
+    for y in 0..height {
+        for x in 0..width {
+            for channel in 0..4 {
+                let mut sum = 0;
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let sx = (x * 2 + dx).min(image.width - 1);
+                    let sy = (y * 2 + dy).min(image.height - 1);
+                    sum += image.pixels[((sx + sy * image.width) * 4 + channel) as usize] as u32;
+                }
+                pixels.push(((sum + 2) / 4) as u8);
+            }
         }
     }
+
+    super::image::Image {
+        width,
+        height,
+        pixels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downsample_averages_quads() {
+        let image = super::super::image::Image {
+            width: 2,
+            height: 2,
+            pixels: vec![0, 0, 0, 255, 255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255],
+        };
+
+        let level = downsample(&image);
+        assert_eq!((level.width, level.height), (1, 1));
+        assert_eq!(level.pixels, vec![64, 64, 64, 255]);
+    }
 }
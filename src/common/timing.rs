@@ -0,0 +1,157 @@
+// Fixed simulation ticks decoupled from the render rate, and frame time stats.
+
+// Longest frame the simulation catches up on, so a stall doesn't make it run
+// ticks forever trying to catch up
+const MAX_FRAME_TIME: std::time::Duration = std::time::Duration::from_millis(250);
+
+pub struct FixedTimestep {
+    pub tick: std::time::Duration,
+    accumulator: std::time::Duration,
+    last: std::time::Instant,
+}
+
+impl FixedTimestep {
+    pub fn new(ticks_per_second: f32) -> Self {
+        Self {
+            tick: std::time::Duration::from_nanos((1e9 / ticks_per_second as f64) as u64),
+            accumulator: std::time::Duration::ZERO,
+            last: std::time::Instant::now(),
+        }
+    }
+
+    /// Tick length in seconds, the delta time of every simulation step.
+    pub fn dt(&self) -> f32 {
+        self.tick.as_secs_f32()
+    }
+
+    /// Number of ticks to simulate for the time since the last call, and how
+    /// far the frame is between the last two ticks for interpolation.
+    pub fn advance(&mut self) -> (u32, f32) {
+        let now = std::time::Instant::now();
+        let elapsed = now - self.last;
+        self.last = now;
+        self.advance_by(elapsed)
+    }
+
+    pub fn advance_by(&mut self, elapsed: std::time::Duration) -> (u32, f32) {
+        self.accumulator += elapsed.min(MAX_FRAME_TIME);
+
+        let mut ticks = 0;
+        while self.accumulator >= self.tick {
+            self.accumulator -= self.tick;
+            ticks += 1;
+        }
+
+        (ticks, self.accumulator.as_secs_f32() / self.dt())
+    }
+}
+
+pub struct FrameStats {
+    frame_times: Vec<std::time::Duration>,
+    last_frame: std::time::Instant,
+    last_report: std::time::Instant,
+    report_interval: std::time::Duration,
+    // Chunks of the last frame
+    chunks: super::frustum::CullStats,
+}
+
+impl FrameStats {
+    pub fn new(report_interval: std::time::Duration) -> Self {
+        let now = std::time::Instant::now();
+        Self {
+            frame_times: Vec::new(),
+            last_frame: now,
+            last_report: now,
+            report_interval,
+            chunks: super::frustum::CullStats::default(),
+        }
+    }
+
+    /// Records the time since the previous frame and logs a summary every
+    /// report interval.
+    pub fn frame(&mut self) {
+        let now = std::time::Instant::now();
+        self.frame_times.push(now - self.last_frame);
+        self.last_frame = now;
+
+        if now - self.last_report >= self.report_interval {
+            log::info!("{}", self.summary());
+            self.frame_times.clear();
+            self.last_report = now;
+        }
+    }
+
+    pub fn chunks(&mut self, chunks: super::frustum::CullStats) {
+        self.chunks = chunks;
+    }
+
+    pub fn fps(&self) -> f32 {
+        let total = self.frame_times.iter().sum::<std::time::Duration>();
+        if total.is_zero() {
+            return 0.0;
+        }
+        self.frame_times.len() as f32 / total.as_secs_f32()
+    }
+
+    /// Frame time below which `percentile` percent of the frames stay.
+    pub fn percentile(&self, percentile: f32) -> std::time::Duration {
+        if self.frame_times.is_empty() {
+            return std::time::Duration::ZERO;
+        }
+
+        let mut sorted = self.frame_times.clone();
+        sorted.sort();
+        let rank = (percentile / 100.0 * sorted.len() as f32).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    }
+
+    pub fn summary(&self) -> String {
+        let ms = |percentile| self.percentile(percentile).as_secs_f32() * 1000.0;
+        format!(
+            "{:.1} FPS, frame time p50 {:.2} ms, p95 {:.2} ms, p99 {:.2} ms, max {:.2} ms, \
+             {} chunks drawn, {} culled",
+            self.fps(),
+            ms(50.0),
+            ms(95.0),
+            ms(99.0),
+            ms(100.0),
+            self.chunks.drawn,
+            self.chunks.culled
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn runs_whole_ticks_and_keeps_the_remainder() {
+        let mut timestep = FixedTimestep::new(20.0);
+
+        assert_eq!(timestep.advance_by(Duration::from_millis(30)).0, 0);
+
+        let (ticks, alpha) = timestep.advance_by(Duration::from_millis(95));
+        assert_eq!(ticks, 2);
+        assert!((alpha - 0.5).abs() < 1e-3, "{}", alpha);
+    }
+
+    #[test]
+    fn caps_long_frames() {
+        let mut timestep = FixedTimestep::new(60.0);
+        let (ticks, _) = timestep.advance_by(Duration::from_secs(10));
+        assert_eq!(ticks, 15);
+    }
+
+    #[test]
+    fn frame_time_percentiles() {
+        let mut stats = FrameStats::new(Duration::from_secs(60));
+        stats.frame_times = (1..=100).map(Duration::from_millis).collect();
+
+        assert_eq!(stats.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(stats.percentile(99.0), Duration::from_millis(99));
+        assert_eq!(stats.percentile(100.0), Duration::from_millis(100));
+        assert!((stats.fps() - 100.0 / 5.05).abs() < 1e-3);
+    }
+}
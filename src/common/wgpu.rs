@@ -1,9 +1,14 @@
 pub struct WgpuManager {
-    pub surface: wgpu::Surface,
+    // Headless managers render into offscreen textures instead
+    pub surface: Option<wgpu::Surface>,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
+    // Features missing on downlevel backends like GL
+    pub downlevel_flags: wgpu::DownlevelFlags,
+    // Copies captured frames onto the surface, made by the first capture
+    blit: Option<Blit>,
 }
 
 impl WgpuManager {
@@ -44,11 +49,67 @@ impl WgpuManager {
         surface.configure(&device, &config);
 
         Self {
-            surface,
+            surface: Some(surface),
             device,
             queue,
             config,
             size,
+            downlevel_flags: adapter.get_downlevel_capabilities().flags,
+            blit: None,
+        }
+    }
+
+    /// Creates a manager without a window, preferring the software fallback
+    /// adapter so rendering also works on CI machines without a GPU.
+    pub async fn new_headless(size: winit::dpi::PhysicalSize<u32>) -> Self {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+
+        let mut adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                force_fallback_adapter: true,
+                compatible_surface: None,
+            })
+            .await;
+        if adapter.is_none() {
+            log::warn!("No fallback adapter, using the default one");
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions::default())
+                .await;
+        }
+        let adapter = adapter.expect("No adapter available for headless rendering");
+        log::info!("Headless adapter: {:?}", adapter.get_info());
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    features: wgpu::Features::default(),
+                    limits: wgpu::Limits::default(),
+                },
+                None,
+            )
+            .await
+            .unwrap();
+
+        // Only used to size and format the offscreen targets
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+        };
+
+        Self {
+            surface: None,
+            device,
+            queue,
+            config,
+            size,
+            downlevel_flags: adapter.get_downlevel_capabilities().flags,
+            blit: None,
         }
     }
 
@@ -56,13 +117,18 @@ impl WgpuManager {
         &mut self,
         new_size: winit::dpi::PhysicalSize<u32>,
         bundles_manager: &mut super::bundles::BundleManager,
+        camera_manager: &mut super::camera::CameraManager,
         window: &winit::window::Window,
     ) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            // Orthographic views use the aspect for their width too
+            camera_manager.camera.aspect = new_size.width as f32 / new_size.height as f32;
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.config);
+            }
             bundles_manager.set_depth_texture(super::texture::Texture::create_depth_texture(
                 &self.device,
                 &self.config,
@@ -77,29 +143,29 @@ impl WgpuManager {
         event: &winit::event::WindowEvent,
         camera_manager: &mut super::camera::CameraManager,
     ) -> bool {
-        camera_manager.camera_controller.process_events(event)
-    }
-
-    pub fn update(&mut self, camera_manager: &mut super::camera::CameraManager) {
         camera_manager
             .camera_controller
-            .update_camera(&mut camera_manager.camera);
-        camera_manager
-            .camera_uniform
-            .update_view_proj(&camera_manager.camera);
-        self.queue.write_buffer(
-            &camera_manager.camera_buffer,
-            0,
-            bytemuck::cast_slice_mut(&mut [camera_manager.camera_uniform]),
-        )
+            .process_events(event, self.size)
+    }
+
+    /// Per frame uploads, `alpha` of the way between the last two ticks.
+    pub fn update(&mut self, camera_manager: &mut super::camera::CameraManager, alpha: f32) {
+        camera_manager.update_uniform(&self.queue, alpha);
     }
 
+    /// Presents a frame, also starting to read it back when `capture` is set.
     pub fn render(
         &mut self,
-        bundles: &[wgpu::RenderBundle],
+        bundles: &[&wgpu::RenderBundle],
         depth_view: &wgpu::TextureView,
-    ) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
+        mut culling: Option<&mut super::culling::GpuCulling>,
+        capture: bool,
+    ) -> Result<Option<PendingImage>, wgpu::SurfaceError> {
+        let output = self
+            .surface
+            .as_ref()
+            .ok_or(wgpu::SurfaceError::Lost)?
+            .get_current_texture()?;
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
@@ -110,38 +176,393 @@ impl WgpuManager {
                 label: Some("Render Encoder"),
             });
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 0.0,
-                        }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: depth_view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
+        if let Some(culling) = &mut culling {
+            culling.encode_cull(&self.device, &mut encoder);
+        }
+
+        // Swapchain textures can't always be copied from, so captured frames
+        // are drawn offscreen and copied onto the surface
+        let texture = if capture {
+            let texture = self.create_offscreen_texture();
+            let offscreen_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            Self::render_pass(&mut encoder, &offscreen_view, bundles, depth_view);
+
+            let blit = self
+                .blit
+                .get_or_insert_with(|| Blit::new(&self.device, self.config.format));
+            blit.encode(&self.device, &mut encoder, &offscreen_view, &view);
+            Some(texture)
+        } else {
+            Self::render_pass(&mut encoder, &view, bundles, depth_view);
+            None
+        };
+
+        if let Some(culling) = &mut culling {
+            culling.encode_hi_z(&self.device, &mut encoder, depth_view, self.config_size());
+        }
+
+        let image = match texture {
+            Some(texture) => Some(self.read_texture(encoder, &texture)),
+            None => {
+                self.queue.submit(std::iter::once(encoder.finish()));
+                None
+            }
+        };
+        output.present();
+        if let Some(culling) = culling {
+            culling.map_stats();
+        }
+
+        Ok(image)
+    }
+
+    /// Renders the bundles into an offscreen texture and reads it back.
+    pub fn render_to_image(
+        &mut self,
+        bundles: &[&wgpu::RenderBundle],
+        depth_view: &wgpu::TextureView,
+        mut culling: Option<&mut super::culling::GpuCulling>,
+    ) -> super::image::Image {
+        let texture = self.create_offscreen_texture();
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Offscreen Encoder"),
             });
 
-            render_pass.execute_bundles(bundles);
+        if let Some(culling) = &mut culling {
+            culling.encode_cull(&self.device, &mut encoder);
         }
+        Self::render_pass(&mut encoder, &view, bundles, depth_view);
+        if let Some(culling) = &mut culling {
+            culling.encode_hi_z(&self.device, &mut encoder, depth_view, self.config_size());
+        }
+
+        let image = self.read_texture(encoder, &texture).wait(&self.device);
+        texture.destroy();
+        if let Some(culling) = culling {
+            culling.map_stats();
+        }
+        image
+    }
+
+    fn config_size(&self) -> (u32, u32) {
+        (self.config.width, self.config.height)
+    }
+
+    fn create_offscreen_texture(&self) -> wgpu::Texture {
+        self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Texture"),
+            size: wgpu::Extent3d {
+                width: self.config.width,
+                height: self.config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::TEXTURE_BINDING,
+        })
+    }
 
+    // Copies the texture into a mappable buffer after the encoded commands
+    // ran, without waiting for the GPU to get there
+    fn read_texture(
+        &self,
+        mut encoder: wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) -> PendingImage {
+        let (width, height) = (self.config.width, self.config.height);
+        // Rows of a texture copy are padded to 256 bytes
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let bytes_per_row = (width * 4).div_ceil(align) * align;
+
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
 
-        Ok(())
+        let (sender, receiver) = std::sync::mpsc::channel();
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+
+        PendingImage {
+            buffer,
+            receiver,
+            width,
+            height,
+            bytes_per_row,
+            bgra: matches!(
+                self.config.format,
+                wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+            ),
+        }
+    }
+
+    fn render_pass(
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        bundles: &[&wgpu::RenderBundle],
+        depth_view: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.0,
+                        g: 0.0,
+                        b: 0.0,
+                        a: 0.0,
+                    }),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.execute_bundles(bundles.iter().copied());
+    }
+}
+
+/// A frame being copied into a mappable buffer.
+pub struct PendingImage {
+    buffer: wgpu::Buffer,
+    receiver: std::sync::mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>,
+    width: u32,
+    height: u32,
+    bytes_per_row: u32,
+    bgra: bool,
+}
+
+impl PendingImage {
+    /// The frame once the copy finished, none while it is still running.
+    /// Needs the device to be polled to get there.
+    pub fn try_read(&self) -> Option<Result<super::image::Image, wgpu::BufferAsyncError>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result.map(|()| self.read())),
+            Err(std::sync::mpsc::TryRecvError::Empty) => None,
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => Some(Err(wgpu::BufferAsyncError)),
+        }
+    }
+
+    /// Blocks until the copy finished.
+    pub fn wait(self, device: &wgpu::Device) -> super::image::Image {
+        device.poll(wgpu::Maintain::Wait);
+        self.receiver
+            .recv()
+            .unwrap()
+            .expect("Could not map the readback buffer");
+        self.read()
+    }
+
+    fn read(&self) -> super::image::Image {
+        let unpadded_bytes_per_row = self.width as usize * 4;
+        let mut pixels = Vec::with_capacity(unpadded_bytes_per_row * self.height as usize);
+        for row in self
+            .buffer
+            .slice(..)
+            .get_mapped_range()
+            .chunks(self.bytes_per_row as usize)
+        {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row]);
+        }
+        self.buffer.unmap();
+
+        for pixel in pixels.chunks_exact_mut(4) {
+            if self.bgra {
+                pixel.swap(0, 2);
+            }
+            // Matches the window, where frames are presented opaque
+            pixel[3] = 255;
+        }
+
+        super::image::Image {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+}
+
+// Draws a texture onto a target of the same size and format
+struct Blit {
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Blit {
+    fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader - Blit"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../assets/shaders/blit.wgsl").into()),
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render Pipeline - Blit"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self { pipeline }
+    }
+
+    fn encode(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bind Group - Blit"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(source),
+            }],
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass - Blit"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blits_and_reads_back_without_waiting() {
+        let _lock = super::super::headless::RENDER_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let manager = pollster::block_on(WgpuManager::new_headless(winit::dpi::PhysicalSize::new(
+            16, 8,
+        )));
+
+        let pixels = (0..16 * 8)
+            .flat_map(|i| [i as u8 * 2, 255 - i as u8, (i % 16) as u8 * 16, 255])
+            .collect::<Vec<u8>>();
+        let source = manager.device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: 16,
+                height: 8,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: manager.config.format,
+            usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        manager.queue.write_texture(
+            source.as_image_copy(),
+            &pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(16 * 4),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: 16,
+                height: 8,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        let target = manager.create_offscreen_texture();
+        let view = |texture: &wgpu::Texture| texture.create_view(&Default::default());
+        let mut encoder = manager
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        Blit::new(&manager.device, manager.config.format).encode(
+            &manager.device,
+            &mut encoder,
+            &view(&source),
+            &view(&target),
+        );
+        let frame = manager.read_texture(encoder, &target);
+
+        let image = loop {
+            manager.device.poll(wgpu::Maintain::Poll);
+            if let Some(image) = frame.try_read() {
+                break image.unwrap();
+            }
+            std::thread::yield_now();
+        };
+        // sRGB targets convert on the way in and out
+        assert!(image
+            .pixels
+            .iter()
+            .zip(&pixels)
+            .all(|(a, b)| a.abs_diff(*b) <= 1));
     }
 }
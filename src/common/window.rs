@@ -1,20 +1,179 @@
+#[cfg(target_os = "windows")]
 use winit::platform::windows::WindowBuilderExtWindows;
 
+#[cfg(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+use winit::platform::unix::WindowBuilderExtUnix;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WindowMode {
+    Windowed,
+    // Fullscreen window on the current monitor, keeping its video mode
+    Borderless,
+    // Exclusive fullscreen with the video mode closest to the window size
+    Fullscreen,
+}
+
+impl WindowMode {
+    /// Switches between the window and a borderless fullscreen window.
+    pub fn toggled(self) -> Self {
+        match self {
+            WindowMode::Windowed => WindowMode::Borderless,
+            WindowMode::Borderless | WindowMode::Fullscreen => WindowMode::Windowed,
+        }
+    }
+}
+
+pub struct WindowConfig {
+    pub title: String,
+    pub size: winit::dpi::PhysicalSize<u32>,
+    pub min_size: Option<winit::dpi::PhysicalSize<u32>>,
+    pub max_size: Option<winit::dpi::PhysicalSize<u32>>,
+    pub mode: WindowMode,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "Stonehearth 2".to_string(),
+            size: winit::dpi::PhysicalSize::new(1280, 720),
+            min_size: None,
+            max_size: None,
+            mode: WindowMode::Windowed,
+        }
+    }
+}
+
+impl WindowConfig {
+    /// `--title`, `--size 1280x720`, `--fullscreen` and `--borderless`.
+    pub fn from_args() -> Self {
+        let mut config = Self::default();
+
+        if let Some(title) = super::args::arg_value("--title") {
+            config.title = title;
+        }
+        if let Some(size) = size_arg() {
+            config.size = size;
+        }
+        if super::args::has_flag("--fullscreen") {
+            config.mode = WindowMode::Fullscreen;
+        } else if super::args::has_flag("--borderless") {
+            config.mode = WindowMode::Borderless;
+        }
+
+        config
+    }
+}
+
+/// The `--size WIDTHxHEIGHT` argument.
+pub fn size_arg() -> Option<winit::dpi::PhysicalSize<u32>> {
+    let size = super::args::arg_value("--size")?;
+    let (width, height) = size.split_once('x')?;
+    let size = winit::dpi::PhysicalSize::new(width.parse().ok()?, height.parse().ok()?);
+    (size.width > 0 && size.height > 0).then_some(size)
+}
+
 pub struct WindowManager {
     pub event_loop: winit::event_loop::EventLoop<()>,
     pub window: winit::window::Window,
+    pub mode: WindowMode,
 }
 
 impl WindowManager {
-    pub fn new() -> Self {
+    pub fn new(config: &WindowConfig) -> Self {
         let event_loop = winit::event_loop::EventLoop::new();
-        let window = winit::window::WindowBuilder::new()
-            .with_title("Stonehearth 2")
-            .with_min_inner_size(winit::dpi::PhysicalSize::new(800, 600))
-            .with_max_inner_size(winit::dpi::PhysicalSize::new(1920, 1080))
-            .with_theme(Some(winit::window::Theme::Dark))
-            .build(&event_loop)
-            .unwrap();
-        Self { event_loop, window }
+
+        let mut builder = winit::window::WindowBuilder::new()
+            .with_title(&config.title)
+            .with_inner_size(config.size);
+        if let Some(min_size) = config.min_size {
+            builder = builder.with_min_inner_size(min_size);
+        }
+        if let Some(max_size) = config.max_size {
+            builder = builder.with_max_inner_size(max_size);
+        }
+
+        #[cfg(target_os = "windows")]
+        {
+            builder = builder.with_theme(Some(winit::window::Theme::Dark));
+        }
+
+        // Groups the windows in the task bar and lets window rules match them,
+        // through WM_CLASS on X11 and the app id on Wayland
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "openbsd"
+        ))]
+        {
+            builder = builder.with_name("stonehearth_2", "stonehearth_2");
+        }
+
+        let window = builder.build(&event_loop).unwrap();
+
+        set_mode(&window, config.mode);
+
+        Self {
+            event_loop,
+            window,
+            mode: config.mode,
+        }
     }
 }
+
+// Not a method so the event loop closure can use it while the event loop is
+// moved out of the window manager
+pub fn set_mode(window: &winit::window::Window, mode: WindowMode) {
+    let fullscreen = match mode {
+        WindowMode::Windowed => None,
+        WindowMode::Borderless => Some(winit::window::Fullscreen::Borderless(None)),
+        WindowMode::Fullscreen => {
+            let size = window.inner_size();
+            let video_mode = window.current_monitor().and_then(|monitor| {
+                monitor.video_modes().min_by_key(|video_mode| {
+                    let mode_size = video_mode.size();
+                    (
+                        mode_size.width.abs_diff(size.width)
+                            + mode_size.height.abs_diff(size.height),
+                        u32::MAX - video_mode.refresh_rate_millihertz(),
+                    )
+                })
+            });
+
+            match video_mode {
+                Some(video_mode) => Some(winit::window::Fullscreen::Exclusive(video_mode)),
+                // Wayland doesn't expose video modes
+                None => {
+                    log::warn!("No video modes available, using a borderless window");
+                    Some(winit::window::Fullscreen::Borderless(None))
+                }
+            }
+        }
+    };
+
+    window.set_fullscreen(fullscreen);
+}
+
+/// Hides and captures the cursor for mouse-look, or releases it.
+pub fn grab_cursor(window: &winit::window::Window, grab: bool) {
+    let result = if grab {
+        // X11 can only confine the cursor, macOS can only lock it
+        window
+            .set_cursor_grab(winit::window::CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(winit::window::CursorGrabMode::Confined))
+    } else {
+        window.set_cursor_grab(winit::window::CursorGrabMode::None)
+    };
+    if let Err(e) = result {
+        log::warn!("Could not grab the cursor: {}", e);
+    }
+
+    window.set_cursor_visible(!grab);
+}
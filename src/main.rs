@@ -6,6 +6,15 @@ fn main() {
 
     println!("Hello StoneHearth 2!");
 
+    // `--screenshot out.png` renders a single frame without opening a window
+    if let Some(path) = common::args::arg_value("--screenshot") {
+        if let Err(e) = pollster::block_on(common::headless::screenshot(path.as_ref())) {
+            log::error!("Could not save screenshot: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let state = pollster::block_on(common::state::State::new());
     state.run();
 }
@@ -0,0 +1,395 @@
+use wgpu::util::DeviceExt;
+
+pub type BlockId = u16;
+
+pub const AIR: BlockId = 0;
+pub const MAX_BLOCKS: usize = 128;
+
+pub const FLAG_SOLID: u32 = 1;
+pub const FLAG_TRANSPARENT: u32 = 1 << 1;
+pub const FLAG_EMISSIVE: u32 = 1 << 2;
+
+// Block textures are layers of one texture array, so they share a size
+pub const TEXTURE_SIZE: u32 = 16;
+// Layers are packed into bytes of the block flags, the first one is white
+// for faces without a texture
+pub const MAX_TEXTURES: usize = 256;
+pub const NO_TEXTURE: u32 = 0;
+
+macro_rules! texture {
+    ($name:literal) => {
+        (
+            $name,
+            include_bytes!(concat!("../../assets/textures/", $name, ".png")),
+        )
+    };
+}
+
+// Built in textures, multiplied with the face colors
+const TEXTURES: &[(&str, &[u8])] = &[
+    texture!("dirt"),
+    texture!("grass_top"),
+    texture!("sand"),
+    texture!("stone"),
+    texture!("wood_side"),
+    texture!("wood_top"),
+];
+
+pub struct Block {
+    pub name: String,
+    pub colors: [[f32; 4]; 6],
+    // Names of registered textures per face
+    pub textures: [Option<String>; 6],
+    pub solid: bool,
+    pub transparent: bool,
+    pub emissive: bool,
+}
+
+impl Block {
+    pub fn new(name: &str, color: [f32; 4]) -> Self {
+        Self {
+            name: name.to_string(),
+            colors: [color; 6],
+            textures: Default::default(),
+            solid: true,
+            transparent: color[3] < 1.0,
+            emissive: false,
+        }
+    }
+
+    pub fn with_face_color(mut self, face: u32, color: [f32; 4]) -> Self {
+        self.colors[face as usize] = color;
+        self
+    }
+
+    pub fn with_texture(mut self, texture: &str) -> Self {
+        self.textures = std::array::from_fn(|_| Some(texture.to_string()));
+        self
+    }
+
+    pub fn with_face_texture(mut self, face: u32, texture: &str) -> Self {
+        self.textures[face as usize] = Some(texture.to_string());
+        self
+    }
+
+    pub fn is_opaque(&self) -> bool {
+        self.solid && !self.transparent
+    }
+
+    // Average over the faces, for formats with a single color per voxel
+    pub fn color(&self) -> [f32; 4] {
+        self.colors.iter().fold([0.0; 4], |mut sum, face| {
+            for (s, c) in sum.iter_mut().zip(face) {
+                *s += c / 6.0;
+            }
+            sum
+        })
+    }
+
+    fn to_raw(&self, texture_ids: &std::collections::HashMap<String, u32>) -> BlockRaw {
+        let mut flags = 0;
+        if self.solid {
+            flags |= FLAG_SOLID;
+        }
+        if self.transparent {
+            flags |= FLAG_TRANSPARENT;
+        }
+        if self.emissive {
+            flags |= FLAG_EMISSIVE;
+        }
+
+        // One byte per face, faces 0 to 3 in the first word and 4 and 5 in the second
+        let mut layers = [0; 2];
+        for (face, texture) in self.textures.iter().enumerate() {
+            let layer = texture
+                .as_ref()
+                .and_then(|name| texture_ids.get(name).copied())
+                .unwrap_or(NO_TEXTURE);
+            layers[face / 4] |= layer << (face % 4 * 8);
+        }
+
+        BlockRaw {
+            colors: self.colors,
+            flags: [flags, layers[0], layers[1], 0],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BlockRaw {
+    colors: [[f32; 4]; 6],
+    flags: [u32; 4],
+}
+
+pub struct BlockRegistry {
+    blocks: Vec<Block>,
+    ids: std::collections::HashMap<String, BlockId>,
+    textures: Vec<crate::common::image::Image>,
+    texture_ids: std::collections::HashMap<String, u32>,
+    dirty: bool,
+}
+
+const COLOR_PREFIX: &str = "color #";
+
+impl BlockRegistry {
+    pub fn new() -> Self {
+        use super::voxel::face::{FACE_DOWN, FACE_UP};
+
+        let mut registry = Self {
+            blocks: Vec::new(),
+            ids: std::collections::HashMap::new(),
+            textures: Vec::new(),
+            texture_ids: std::collections::HashMap::new(),
+            dirty: false,
+        };
+
+        registry.register_texture(
+            "white",
+            crate::common::image::Image {
+                width: TEXTURE_SIZE,
+                height: TEXTURE_SIZE,
+                pixels: vec![255; (TEXTURE_SIZE * TEXTURE_SIZE * 4) as usize],
+            },
+        );
+        for (name, png) in TEXTURES {
+            let image = crate::common::image::Image::read_png(*png)
+                .expect("Built in textures are valid PNGs");
+            registry.register_texture(name, image);
+        }
+
+        registry.register(Block {
+            solid: false,
+            transparent: true,
+            ..Block::new("air", [0.0, 0.0, 0.0, 0.0])
+        });
+        registry.register(
+            Block::new("grass", [0.45, 0.5, 0.3, 1.0])
+                .with_face_color(FACE_UP, [0.3, 0.7, 0.4, 1.0])
+                .with_face_color(FACE_DOWN, [0.45, 0.32, 0.2, 1.0])
+                .with_texture("dirt")
+                .with_face_texture(FACE_UP, "grass_top"),
+        );
+        registry.register(Block::new("dirt", [0.45, 0.32, 0.2, 1.0]).with_texture("dirt"));
+        registry.register(Block::new("stone", [0.5, 0.5, 0.52, 1.0]).with_texture("stone"));
+        registry.register(Block::new("sand", [0.85, 0.8, 0.55, 1.0]).with_texture("sand"));
+        registry.register(Block::new("snow", [0.92, 0.94, 0.97, 1.0]));
+        registry.register(Block::new("water", [0.2, 0.4, 0.8, 0.6]));
+        registry.register(
+            Block::new("wood", [0.4, 0.28, 0.15, 1.0])
+                .with_face_color(FACE_UP, [0.6, 0.45, 0.28, 1.0])
+                .with_face_color(FACE_DOWN, [0.6, 0.45, 0.28, 1.0])
+                .with_texture("wood_side")
+                .with_face_texture(FACE_UP, "wood_top")
+                .with_face_texture(FACE_DOWN, "wood_top"),
+        );
+
+        registry
+    }
+
+    pub fn register(&mut self, block: Block) -> BlockId {
+        if let Some(id) = self.ids.get(&block.name) {
+            return *id;
+        }
+
+        assert!(
+            self.blocks.len() < MAX_BLOCKS,
+            "Block registry is full, cannot register {}",
+            block.name
+        );
+
+        let id = self.blocks.len() as BlockId;
+        self.ids.insert(block.name.clone(), id);
+        self.blocks.push(block);
+        self.dirty = true;
+        id
+    }
+
+    /// Adds a texture blocks can refer to by name and returns its layer.
+    /// Textures have to be registered before the voxel manager uploads them.
+    pub fn register_texture(&mut self, name: &str, image: crate::common::image::Image) -> u32 {
+        if let Some(layer) = self.texture_ids.get(name) {
+            return *layer;
+        }
+
+        assert!(
+            image.width == TEXTURE_SIZE && image.height == TEXTURE_SIZE,
+            "Texture {} is {}x{}, block textures are {}x{}",
+            name,
+            image.width,
+            image.height,
+            TEXTURE_SIZE,
+            TEXTURE_SIZE
+        );
+        assert!(
+            self.textures.len() < MAX_TEXTURES,
+            "Too many textures, cannot register {}",
+            name
+        );
+
+        let layer = self.textures.len() as u32;
+        self.texture_ids.insert(name.to_string(), layer);
+        self.textures.push(image);
+        self.dirty = true;
+        layer
+    }
+
+    pub fn textures(&self) -> &[crate::common::image::Image] {
+        &self.textures
+    }
+
+    // Blocks that only carry a color, e.g. for imported models. The color is
+    // part of the name so saved worlds can recreate them.
+    pub fn register_color(&mut self, color: [u8; 4]) -> BlockId {
+        match self.try_register_color(color) {
+            Some(id) => id,
+            None => {
+                log::warn!(
+                    "Block registry is full, using the nearest color for {:?}",
+                    color
+                );
+                self.nearest(color)
+            }
+        }
+    }
+
+    /// Like `register_color`, but none instead of another block when the
+    /// registry is full.
+    pub fn try_register_color(&mut self, color: [u8; 4]) -> Option<BlockId> {
+        let name = format!(
+            "{}{:02x}{:02x}{:02x}{:02x}",
+            COLOR_PREFIX, color[0], color[1], color[2], color[3]
+        );
+        if let Some(id) = self.get_id(&name) {
+            return Some(id);
+        }
+
+        if self.blocks.len() >= MAX_BLOCKS {
+            return None;
+        }
+
+        Some(self.register(Block::new(&name, color.map(|c| c as f32 / 255.0))))
+    }
+
+    pub fn nearest(&self, color: [u8; 4]) -> BlockId {
+        let color = color.map(|c| c as f32 / 255.0);
+
+        let mut nearest = AIR;
+        let mut nearest_distance = f32::MAX;
+        for (id, block) in self.blocks.iter().enumerate().filter(|(_, b)| b.solid) {
+            let distance = block
+                .color()
+                .iter()
+                .zip(color.iter())
+                .map(|(a, c)| (a - c).powi(2))
+                .sum::<f32>();
+
+            if distance < nearest_distance {
+                nearest = id as BlockId;
+                nearest_distance = distance;
+            }
+        }
+        nearest
+    }
+
+    /// The block saved as `name`, registering saved colors again. None when
+    /// it is unknown or no more colors fit, saves never load as other blocks.
+    pub fn resolve(&mut self, name: &str) -> Option<BlockId> {
+        if let Some(id) = self.get_id(name) {
+            return Some(id);
+        }
+
+        let hex = name.strip_prefix(COLOR_PREFIX)?;
+        if hex.len() != 8 {
+            return None;
+        }
+        let mut color = [0; 4];
+        for (i, c) in color.iter_mut().enumerate() {
+            *c = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+        }
+        self.try_register_color(color)
+    }
+
+    pub fn get(&self, id: BlockId) -> &Block {
+        &self.blocks[id as usize]
+    }
+
+    pub fn get_id(&self, name: &str) -> Option<BlockId> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn is_opaque(&self, id: BlockId) -> bool {
+        self.get(id).is_opaque()
+    }
+
+    pub fn create_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Buffer - Block Registry"),
+            contents: bytemuck::cast_slice(&self.to_raw()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+
+    pub fn update_buffer(&mut self, queue: &wgpu::Queue, buffer: &wgpu::Buffer) {
+        if self.dirty {
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&self.to_raw()));
+            self.dirty = false;
+        }
+    }
+
+    fn to_raw(&self) -> Vec<BlockRaw> {
+        let mut raw = self
+            .blocks
+            .iter()
+            .map(|block| block.to_raw(&self.texture_ids))
+            .collect::<Vec<_>>();
+        raw.resize(MAX_BLOCKS, bytemuck::Zeroable::zeroed());
+        raw
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::voxel::face::{FACE_DOWN, FACE_UP};
+
+    #[test]
+    fn packs_texture_layers_per_face() {
+        let registry = BlockRegistry::new();
+        let raw = registry
+            .get(registry.get_id("grass").unwrap())
+            .to_raw(&registry.texture_ids);
+
+        let dirt = registry.texture_ids["dirt"];
+        let grass_top = registry.texture_ids["grass_top"];
+        let layer = |face: u32| (raw.flags[1 + face as usize / 4] >> (face % 4 * 8)) & 0xff;
+        assert_eq!(layer(0), dirt);
+        assert_eq!(layer(FACE_UP), grass_top);
+        assert_eq!(layer(FACE_DOWN), dirt);
+
+        // Untextured faces use the white layer
+        let snow = registry
+            .get(registry.get_id("snow").unwrap())
+            .to_raw(&registry.texture_ids);
+        assert_eq!(&snow.flags[1..3], &[NO_TEXTURE; 2]);
+    }
+
+    #[test]
+    fn resolves_no_colors_once_full() {
+        let mut registry = BlockRegistry::new();
+        let mut gray = 0;
+        while registry.blocks.len() < MAX_BLOCKS {
+            registry.register_color([gray, gray, gray, 255]);
+            gray += 1;
+        }
+
+        let name = format!("{}ff00ffff", COLOR_PREFIX);
+        assert_eq!(registry.resolve(&name), None);
+        // Saved colors that made it in before still resolve
+        assert!(registry
+            .resolve(&format!("{}000000ff", COLOR_PREFIX))
+            .is_some());
+        // Imports still fall back to the nearest color
+        assert_ne!(registry.register_color([255, 0, 255, 255]), AIR);
+    }
+}
@@ -0,0 +1,199 @@
+use super::block::{BlockId, AIR};
+use super::voxel::face::FaceInstanceRaw;
+use wgpu::util::DeviceExt;
+
+pub const CHUNK_SIZE: i32 = 32;
+pub const CHUNK_VOLUME: usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+pub fn chunk_position(position: cgmath::Vector3<i32>) -> cgmath::Vector3<i32> {
+    cgmath::vec3(
+        position.x.div_euclid(CHUNK_SIZE),
+        position.y.div_euclid(CHUNK_SIZE),
+        position.z.div_euclid(CHUNK_SIZE),
+    )
+}
+
+pub fn local_position(position: cgmath::Vector3<i32>) -> cgmath::Vector3<i32> {
+    cgmath::vec3(
+        position.x.rem_euclid(CHUNK_SIZE),
+        position.y.rem_euclid(CHUNK_SIZE),
+        position.z.rem_euclid(CHUNK_SIZE),
+    )
+}
+
+pub struct Chunk {
+    pub position: cgmath::Vector3<i32>,
+    pub instances_buffer: Option<wgpu::Buffer>,
+    // Instances are local to the chunk, this places them
+    pub origin_bind_group: Option<wgpu::BindGroup>,
+    pub mesh: super::mesher::ChunkMesh,
+    pub bundle: Option<usize>,
+    voxels: Vec<BlockId>,
+    voxel_count: u32,
+    instance_data: Vec<FaceInstanceRaw>,
+}
+
+impl Chunk {
+    pub fn new(position: cgmath::Vector3<i32>) -> Self {
+        Self {
+            position,
+            instances_buffer: None,
+            origin_bind_group: None,
+            mesh: super::mesher::ChunkMesh::default(),
+            bundle: None,
+            voxels: vec![AIR; CHUNK_VOLUME],
+            voxel_count: 0,
+            instance_data: Vec::new(),
+        }
+    }
+
+    pub fn from_blocks(position: cgmath::Vector3<i32>, blocks: Vec<BlockId>) -> Self {
+        let mut chunk = Self::new(position);
+        chunk.set_blocks(blocks);
+        chunk
+    }
+
+    pub fn set_blocks(&mut self, blocks: Vec<BlockId>) {
+        assert_eq!(blocks.len(), CHUNK_VOLUME);
+
+        self.voxel_count = blocks.iter().filter(|block| **block != AIR).count() as u32;
+        self.voxels = blocks;
+    }
+
+    pub fn blocks(&self) -> &[BlockId] {
+        &self.voxels
+    }
+
+    fn index(local: cgmath::Vector3<i32>) -> usize {
+        (local.x + local.z * CHUNK_SIZE + local.y * CHUNK_SIZE * CHUNK_SIZE) as usize
+    }
+
+    pub fn origin(&self) -> cgmath::Vector3<i32> {
+        self.position * CHUNK_SIZE
+    }
+
+    /// Corners of the space the chunk's voxels can fill.
+    pub fn aabb(&self) -> (cgmath::Point3<f32>, cgmath::Point3<f32>) {
+        let origin = self.origin();
+        let min = cgmath::point3(origin.x as f32, origin.y as f32, origin.z as f32);
+        let size = CHUNK_SIZE as f32;
+        (min, min + cgmath::vec3(size, size, size))
+    }
+
+    pub fn get_voxel(&self, local: cgmath::Vector3<i32>) -> BlockId {
+        self.voxels[Self::index(local)]
+    }
+
+    pub fn set_voxel(&mut self, local: cgmath::Vector3<i32>, block: BlockId) {
+        let voxel = &mut self.voxels[Self::index(local)];
+        match (*voxel == AIR, block == AIR) {
+            (true, false) => self.voxel_count += 1,
+            (false, true) => self.voxel_count -= 1,
+            _ => {}
+        }
+        *voxel = block;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voxel_count == 0
+    }
+
+    pub fn voxels(&self) -> impl Iterator<Item = (cgmath::Vector3<i32>, BlockId)> + '_ {
+        let origin = self.origin();
+        self.voxels
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| **block != AIR)
+            .map(move |(i, block)| {
+                let i = i as i32;
+                let position = origin
+                    + cgmath::vec3(
+                        i % CHUNK_SIZE,
+                        i / (CHUNK_SIZE * CHUNK_SIZE),
+                        (i / CHUNK_SIZE) % CHUNK_SIZE,
+                    );
+                (position, *block)
+            })
+    }
+
+    pub fn set_instance_data(&mut self, voxels: &[super::voxel::Voxel]) {
+        self.instance_data = voxels
+            .iter()
+            .flat_map(|v| v.get_data().iter().copied())
+            .collect();
+    }
+
+    pub fn instance_data(&self) -> &[FaceInstanceRaw] {
+        &self.instance_data
+    }
+
+    pub fn instance_count(&self) -> u32 {
+        self.instance_data.len() as u32
+    }
+
+    /// Uploads the instances, and the origin the first time as it never changes.
+    pub fn update_buffers(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        origin_layout: &wgpu::BindGroupLayout,
+    ) {
+        write_buffer(
+            device,
+            queue,
+            &mut self.instances_buffer,
+            "Instance Buffer - Chunk",
+            bytemuck::cast_slice(&self.instance_data),
+            wgpu::BufferUsages::VERTEX,
+        );
+
+        if self.origin_bind_group.is_none() {
+            let origin = self.origin();
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Origin Buffer - Chunk"),
+                contents: bytemuck::cast_slice(&[origin.x, origin.y, origin.z, 0]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            self.origin_bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Bind Group - Chunk Origin"),
+                layout: origin_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+            }));
+        }
+    }
+}
+
+// Reuses the buffer through `queue.write_buffer` while the data fits, otherwise
+// grows it to the next power of two so repeated edits don't reallocate.
+pub fn write_buffer(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &mut Option<wgpu::Buffer>,
+    label: &str,
+    contents: &[u8],
+    usage: wgpu::BufferUsages,
+) {
+    if contents.is_empty() {
+        return;
+    }
+
+    let size = contents.len() as wgpu::BufferAddress;
+    if buffer.as_ref().is_none_or(|b| b.size() < size) {
+        if let Some(old) = buffer.take() {
+            old.destroy();
+        }
+        *buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: size.next_power_of_two(),
+            usage: usage | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+    }
+
+    if let Some(buffer) = buffer {
+        queue.write_buffer(buffer, 0, contents);
+    }
+}
@@ -0,0 +1,439 @@
+// Exports the visible voxel faces as glTF 2.0 or Wavefront OBJ, e.g. for
+// rendering in Blender or comparing meshes in tests without a GPU.
+//
+// Faces are grouped by block so every block becomes one material, colored
+// with the average of its face colors.
+
+use std::io::Write;
+
+use super::block::{BlockId, BlockRegistry};
+use super::voxel::face::{self, FaceInstanceRaw};
+
+pub struct MeshGroup {
+    pub block: BlockId,
+    pub name: String,
+    pub color: [f32; 4],
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    // Counter-clockwise when seen from outside, like glTF and OBJ expect
+    pub indices: Vec<u32>,
+}
+
+#[derive(Default)]
+pub struct MeshExport {
+    pub groups: Vec<MeshGroup>,
+}
+
+impl MeshExport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.groups.iter().map(|g| g.positions.len()).sum()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.groups.iter().map(|g| g.indices.len() / 3).sum()
+    }
+
+    fn group(&mut self, registry: &BlockRegistry, block: BlockId) -> &mut MeshGroup {
+        let index = match self.groups.iter().position(|g| g.block == block) {
+            Some(index) => index,
+            None => {
+                let definition = registry.get(block);
+                self.groups.push(MeshGroup {
+                    block,
+                    name: definition.name.clone(),
+                    color: definition.color(),
+                    positions: Vec::new(),
+                    normals: Vec::new(),
+                    indices: Vec::new(),
+                });
+                self.groups.len() - 1
+            }
+        };
+        &mut self.groups[index]
+    }
+
+    fn push_quad(
+        &mut self,
+        registry: &BlockRegistry,
+        block: BlockId,
+        face: u32,
+        corners: [[f32; 3]; 4],
+    ) {
+        let group = self.group(registry, block);
+        let base = group.positions.len() as u32;
+
+        group.positions.extend_from_slice(&corners);
+        group.normals.extend([face::NORMALS[face as usize]; 4]);
+        group
+            .indices
+            .extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+
+    /// Adds the faces of the instanced path for a chunk at `origin`, as
+    /// produced by `Voxel::get_data`.
+    pub fn push_instances(
+        &mut self,
+        registry: &BlockRegistry,
+        origin: cgmath::Vector3<i32>,
+        instances: &[FaceInstanceRaw],
+    ) {
+        for instance in instances {
+            // The face quad winds clockwise seen from outside, the pipeline culls front faces
+            let quad = instance.corners(origin);
+            let corners = [0, 3, 2, 1].map(|i| quad[i]);
+            self.push_quad(registry, instance.block(), instance.face(), corners);
+        }
+    }
+
+    /// Adds the quads of a greedy chunk mesh.
+    pub fn push_mesh(&mut self, registry: &BlockRegistry, mesh: &super::mesher::ChunkMesh) {
+        for quad in mesh.indices.chunks_exact(6) {
+            let base = quad[0];
+            // Quads facing negative axes are wound the other way around
+            let order = if quad[1] == base + 1 {
+                [0, 1, 2, 3]
+            } else {
+                [0, 3, 2, 1]
+            };
+
+            let vertex = mesh.vertices[base as usize];
+            let corners = order.map(|i| mesh.vertices[(base + i) as usize].position);
+            self.push_quad(registry, vertex.block as BlockId, vertex.face, corners);
+        }
+    }
+
+    /// Writes `<path>.gltf` next to its `<path>.bin` buffer.
+    pub fn save_gltf(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let bin_path = path.with_extension("bin");
+        let bin_name = file_name(&bin_path);
+
+        let mut gltf = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_gltf(&mut gltf, &bin_name)?;
+        gltf.flush()?;
+
+        let mut bin = std::io::BufWriter::new(std::fs::File::create(bin_path)?);
+        self.write_bin(&mut bin)?;
+        bin.flush()
+    }
+
+    /// Writes the glTF JSON referencing the buffer written by `write_bin` as
+    /// `bin_name`.
+    pub fn write_gltf<W: Write>(&self, writer: &mut W, bin_name: &str) -> std::io::Result<()> {
+        let mut buffer_views = Vec::new();
+        let mut accessors = Vec::new();
+        let mut primitives = Vec::new();
+        let mut materials = Vec::new();
+        let mut offset = 0;
+
+        let mut view = |length: usize, target: u32| {
+            buffer_views.push(format!(
+                r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+                offset, length, target
+            ));
+            offset += length;
+            buffer_views.len() - 1
+        };
+
+        for (material, group) in self.groups.iter().enumerate() {
+            let (min, max) = bounds(&group.positions);
+            let count = group.positions.len();
+
+            let position = accessors.len();
+            accessors.push(format!(
+                r#"{{"bufferView":{},"componentType":5126,"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+                view(count * 12, ARRAY_BUFFER),
+                count,
+                min[0],
+                min[1],
+                min[2],
+                max[0],
+                max[1],
+                max[2]
+            ));
+            accessors.push(format!(
+                r#"{{"bufferView":{},"componentType":5126,"count":{},"type":"VEC3"}}"#,
+                view(count * 12, ARRAY_BUFFER),
+                count
+            ));
+            accessors.push(format!(
+                r#"{{"bufferView":{},"componentType":5125,"count":{},"type":"SCALAR"}}"#,
+                view(group.indices.len() * 4, ELEMENT_ARRAY_BUFFER),
+                group.indices.len()
+            ));
+
+            primitives.push(format!(
+                r#"{{"attributes":{{"POSITION":{},"NORMAL":{}}},"indices":{},"material":{}}}"#,
+                position,
+                position + 1,
+                position + 2,
+                material
+            ));
+
+            let [r, g, b, a] = group.color;
+            materials.push(format!(
+                r#"{{"name":"{}","pbrMetallicRoughness":{{"baseColorFactor":[{},{},{},{}],"metallicFactor":0,"roughnessFactor":1}},"alphaMode":"{}"}}"#,
+                escape(&group.name),
+                r,
+                g,
+                b,
+                a,
+                if a < 1.0 { "BLEND" } else { "OPAQUE" }
+            ));
+        }
+
+        let asset = r#""asset":{"version":"2.0","generator":"stonehearth_2"},"scene":0,"scenes":[{"nodes":[0]}]"#;
+
+        // glTF arrays and buffers can't be empty, an empty world is a bare node
+        if primitives.is_empty() {
+            write!(writer, r#"{{{},"nodes":[{{"name":"world"}}]}}"#, asset)?;
+        } else {
+            write!(
+                writer,
+                r#"{{{},"nodes":[{{"mesh":0,"name":"world"}}],"meshes":[{{"primitives":[{}]}}],"materials":[{}],"accessors":[{}],"bufferViews":[{}],"buffers":[{{"uri":"{}","byteLength":{}}}]}}"#,
+                asset,
+                primitives.join(","),
+                materials.join(","),
+                accessors.join(","),
+                buffer_views.join(","),
+                escape(bin_name),
+                offset
+            )?;
+        }
+        writeln!(writer)
+    }
+
+    pub fn write_bin<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        for group in self.groups.iter() {
+            writer.write_all(bytemuck::cast_slice(&group.positions))?;
+            writer.write_all(bytemuck::cast_slice(&group.normals))?;
+            writer.write_all(bytemuck::cast_slice(&group.indices))?;
+        }
+        Ok(())
+    }
+
+    /// Writes `<path>.obj` and the `<path>.mtl` holding its materials.
+    pub fn save_obj(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let mtl_path = path.with_extension("mtl");
+
+        let mut obj = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_obj(&mut obj, &file_name(&mtl_path))?;
+        obj.flush()?;
+
+        let mut mtl = std::io::BufWriter::new(std::fs::File::create(mtl_path)?);
+        self.write_mtl(&mut mtl)?;
+        mtl.flush()
+    }
+
+    pub fn write_obj<W: Write>(&self, writer: &mut W, mtl_name: &str) -> std::io::Result<()> {
+        writeln!(writer, "mtllib {}", mtl_name)?;
+        writeln!(writer, "o world")?;
+
+        // Faces reference one of the six face normals
+        for [x, y, z] in face::NORMALS {
+            writeln!(writer, "vn {} {} {}", x, y, z)?;
+        }
+
+        let mut base = 1;
+        for group in self.groups.iter() {
+            for [x, y, z] in group.positions.iter() {
+                writeln!(writer, "v {} {} {}", x, y, z)?;
+            }
+
+            writeln!(writer, "usemtl {}", material_name(&group.name))?;
+            for triangle in group.indices.chunks_exact(3) {
+                let normal = normal_index(group.normals[triangle[0] as usize]);
+                write!(writer, "f")?;
+                for i in triangle {
+                    write!(writer, " {}//{}", base + i, normal)?;
+                }
+                writeln!(writer)?;
+            }
+
+            base += group.positions.len() as u32;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_mtl<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        for group in self.groups.iter() {
+            let [r, g, b, a] = group.color;
+            writeln!(writer, "newmtl {}", material_name(&group.name))?;
+            writeln!(writer, "Kd {} {} {}", r, g, b)?;
+            writeln!(writer, "d {}", a)?;
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+fn bounds(positions: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
+    positions.iter().fold(
+        ([f32::MAX; 3], [f32::MIN; 3]),
+        |(mut min, mut max), position| {
+            for i in 0..3 {
+                min[i] = min[i].min(position[i]);
+                max[i] = max[i].max(position[i]);
+            }
+            (min, max)
+        },
+    )
+}
+
+fn normal_index(normal: [f32; 3]) -> usize {
+    face::NORMALS.iter().position(|n| *n == normal).unwrap_or(0) + 1
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// OBJ names end at whitespace
+fn material_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn file_name(path: &std::path::Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::voxel::Voxel;
+
+    fn export(voxels: &[Voxel], registry: &BlockRegistry) -> MeshExport {
+        let mut export = MeshExport::new();
+        for voxel in voxels {
+            export.push_instances(registry, cgmath::vec3(0, 0, 0), voxel.get_data());
+        }
+        export
+    }
+
+    #[test]
+    fn skips_hidden_faces() {
+        let registry = BlockRegistry::new();
+        let stone = registry.get_id("stone").unwrap();
+
+        let mut voxel = Voxel::new(cgmath::vec3(0, 0, 0), stone);
+        voxel.set_faces(
+            Some(true),
+            Some(false),
+            Some(false),
+            Some(false),
+            Some(false),
+            Some(true),
+        );
+        voxel.update_instance_data();
+        // Hidden faces aren't instanced at all
+        assert_eq!(voxel.get_data().len(), 4);
+
+        let export = export(&[voxel], &registry);
+        assert_eq!(export.groups.len(), 1);
+        assert_eq!(export.vertex_count(), 16);
+        assert_eq!(export.triangle_count(), 8);
+    }
+
+    fn assert_outwards(export: &MeshExport, center: cgmath::Vector3<f32>) {
+        let group = &export.groups[0];
+        for triangle in group.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| {
+                let p = group.positions[triangle[i] as usize];
+                cgmath::vec3(p[0], p[1], p[2])
+            });
+            let normal = (b - a).cross(c - a);
+            let stored = group.normals[triangle[0] as usize];
+
+            use cgmath::InnerSpace;
+            assert!(normal.dot(cgmath::vec3(stored[0], stored[1], stored[2])) > 0.0);
+            assert!(normal.dot(a - center) > 0.0);
+        }
+    }
+
+    #[test]
+    fn faces_point_outwards() {
+        let registry = BlockRegistry::new();
+        let dirt = registry.get_id("dirt").unwrap();
+        let export = export(&[Voxel::new(cgmath::vec3(2, 3, 4), dirt)], &registry);
+
+        assert_outwards(&export, cgmath::vec3(2.5, 3.5, 4.5));
+    }
+
+    #[test]
+    fn greedy_faces_point_outwards() {
+        let registry = BlockRegistry::new();
+        let dirt = registry.get_id("dirt").unwrap();
+        let mut chunk = crate::world::chunk::Chunk::new(cgmath::vec3(0, 0, 0));
+        chunk.set_voxel(cgmath::vec3(2, 3, 4), dirt);
+
+        let mut export = MeshExport::new();
+        let mesh = crate::world::mesher::ChunkMesh::greedy(&chunk, |_, _, _| false);
+        export.push_mesh(&registry, &mesh);
+
+        assert_eq!(export.triangle_count(), 12);
+        assert_outwards(&export, cgmath::vec3(2.5, 3.5, 4.5));
+    }
+
+    #[test]
+    fn writes_one_material_per_block() {
+        let registry = BlockRegistry::new();
+        let grass = registry.get_id("grass").unwrap();
+        let water = registry.get_id("water").unwrap();
+        let export = export(
+            &[
+                Voxel::new(cgmath::vec3(0, 0, 0), grass),
+                Voxel::new(cgmath::vec3(1, 0, 0), water),
+            ],
+            &registry,
+        );
+
+        let mut gltf = Vec::new();
+        export.write_gltf(&mut gltf, "world.bin").unwrap();
+        let gltf = String::from_utf8(gltf).unwrap();
+        assert!(gltf.contains(r#""name":"grass""#));
+        assert!(gltf.contains(r#""alphaMode":"BLEND""#));
+        assert!(gltf.contains(r#""uri":"world.bin""#));
+
+        // Positions, normals and indices of 24 vertices per cube
+        let mut bin = Vec::new();
+        export.write_bin(&mut bin).unwrap();
+        assert_eq!(bin.len(), 2 * (24 * (12 + 12) + 36 * 4));
+        assert!(gltf.contains(&format!(r#""byteLength":{}}}]"#, bin.len())));
+
+        let mut obj = Vec::new();
+        export.write_obj(&mut obj, "world.mtl").unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        assert_eq!(obj.lines().filter(|l| l.starts_with("v ")).count(), 48);
+        assert_eq!(obj.lines().filter(|l| l.starts_with("f ")).count(), 24);
+        assert!(obj.contains("usemtl water"));
+
+        let mut mtl = Vec::new();
+        export.write_mtl(&mut mtl).unwrap();
+        assert!(String::from_utf8(mtl).unwrap().contains("d 0.6"));
+    }
+
+    #[test]
+    fn writes_an_empty_world_without_empty_arrays() {
+        let mut gltf = Vec::new();
+        MeshExport::new()
+            .write_gltf(&mut gltf, "world.bin")
+            .unwrap();
+        let gltf = String::from_utf8(gltf).unwrap();
+
+        assert!(gltf.contains(r#""nodes":[{"name":"world"}]"#), "{}", gltf);
+        for property in ["meshes", "materials", "accessors", "bufferViews", "buffers"] {
+            assert!(!gltf.contains(property), "{}", gltf);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use super::block::{BlockId, AIR};
+use super::chunk::{Chunk, CHUNK_SIZE};
+use super::voxel::face;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MeshingMode {
+    Instanced,
+    Greedy,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MeshVertex {
+    pub position: [f32; 3],
+    pub uv: [f32; 2],
+    pub block: u32,
+    pub face: u32,
+}
+
+impl MeshVertex {
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<MeshVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+            ],
+        }
+    }
+}
+
+// (axis, normal sign, face index)
+const DIRECTIONS: [(usize, i32, u32); 6] = [
+    (0, 1, face::FACE_RIGHT),
+    (0, -1, face::FACE_LEFT),
+    (1, 1, face::FACE_UP),
+    (1, -1, face::FACE_DOWN),
+    (2, 1, face::FACE_FRONT),
+    (2, -1, face::FACE_BACK),
+];
+
+#[derive(Default)]
+pub struct ChunkMesh {
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Vec<u32>,
+    pub vertex_buffer: Option<wgpu::Buffer>,
+    pub index_buffer: Option<wgpu::Buffer>,
+}
+
+impl ChunkMesh {
+    /// Merges coplanar visible faces of the same block into larger quads.
+    /// `hides` tells whether the neighbour at `position + offset` hides the
+    /// face of `block` pointing towards it.
+    pub fn greedy<F>(chunk: &Chunk, hides: F) -> Self
+    where
+        F: Fn(cgmath::Vector3<i32>, BlockId, cgmath::Vector3<i32>) -> bool,
+    {
+        let size = CHUNK_SIZE as usize;
+        let origin = chunk.origin();
+        let mut mesh = Self::default();
+        let mut mask = vec![AIR; size * size];
+
+        for (axis, sign, face) in DIRECTIONS {
+            let u = (axis + 1) % 3;
+            let v = (axis + 2) % 3;
+
+            let mut offset = cgmath::vec3(0, 0, 0);
+            offset[axis] = sign;
+
+            for slice in 0..CHUNK_SIZE {
+                for b in 0..CHUNK_SIZE {
+                    for a in 0..CHUNK_SIZE {
+                        let mut local = cgmath::vec3(0, 0, 0);
+                        local[axis] = slice;
+                        local[u] = a;
+                        local[v] = b;
+
+                        let block = chunk.get_voxel(local);
+                        mask[a as usize + b as usize * size] =
+                            if block != AIR && !hides(origin + local, block, offset) {
+                                block
+                            } else {
+                                AIR
+                            };
+                    }
+                }
+
+                for b in 0..size {
+                    let mut a = 0;
+                    while a < size {
+                        let block = mask[a + b * size];
+                        if block == AIR {
+                            a += 1;
+                            continue;
+                        }
+
+                        let mut width = 1;
+                        while a + width < size && mask[a + width + b * size] == block {
+                            width += 1;
+                        }
+
+                        let mut height = 1;
+                        'rows: while b + height < size {
+                            for i in 0..width {
+                                if mask[a + i + (b + height) * size] != block {
+                                    break 'rows;
+                                }
+                            }
+                            height += 1;
+                        }
+
+                        for j in 0..height {
+                            for i in 0..width {
+                                mask[a + i + (b + j) * size] = AIR;
+                            }
+                        }
+
+                        let mut corner = cgmath::vec3(0.0, 0.0, 0.0);
+                        corner[axis] = (slice + if sign > 0 { 1 } else { 0 }) as f32;
+                        corner[u] = a as f32;
+                        corner[v] = b as f32;
+                        let corner = corner
+                            + cgmath::vec3(origin.x as f32, origin.y as f32, origin.z as f32);
+
+                        mesh.push_quad(
+                            corner,
+                            (u, width as f32),
+                            (v, height as f32),
+                            sign > 0,
+                            block,
+                            face,
+                        );
+
+                        a += width;
+                    }
+                }
+            }
+        }
+
+        mesh
+    }
+
+    fn push_quad(
+        &mut self,
+        corner: cgmath::Vector3<f32>,
+        (u, width): (usize, f32),
+        (v, height): (usize, f32),
+        positive: bool,
+        block: BlockId,
+        face: u32,
+    ) {
+        let base = self.vertices.len() as u32;
+
+        for (du, dv) in [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)] {
+            let mut position = corner;
+            position[u] += du;
+            position[v] += dv;
+
+            // Along the axes of the instanced face quads, so textures line up
+            // with them once the sampler repeats them per block
+            let [x_axis, y_axis] = super::voxel::face::AXES[face as usize];
+            let uv = [
+                cgmath::dot(position, x_axis.into()),
+                -cgmath::dot(position, y_axis.into()),
+            ];
+
+            self.vertices.push(MeshVertex {
+                position: position.into(),
+                uv,
+                block: block as u32,
+                face,
+            });
+        }
+
+        // Counter-clockwise when seen from the side the face points to
+        if positive {
+            self.indices
+                .extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+        } else {
+            self.indices
+                .extend_from_slice(&[base, base + 2, base + 1, base, base + 3, base + 2]);
+        }
+    }
+
+    pub fn update_buffers(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        super::chunk::write_buffer(
+            device,
+            queue,
+            &mut self.vertex_buffer,
+            "Vertex Buffer - Chunk Mesh",
+            bytemuck::cast_slice(&self.vertices),
+            wgpu::BufferUsages::VERTEX,
+        );
+        super::chunk::write_buffer(
+            device,
+            queue,
+            &mut self.index_buffer,
+            "Index Buffer - Chunk Mesh",
+            bytemuck::cast_slice(&self.indices),
+            wgpu::BufferUsages::INDEX,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A chunk at the origin holding the blocks `filled` returns
+    fn chunk(filled: impl Fn(cgmath::Vector3<i32>) -> Option<BlockId>) -> Chunk {
+        let mut chunk = Chunk::new(cgmath::vec3(0, 0, 0));
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    if let Some(block) = filled(cgmath::vec3(x, y, z)) {
+                        chunk.set_voxel(cgmath::vec3(x, y, z), block);
+                    }
+                }
+            }
+        }
+        chunk
+    }
+
+    fn mesh(chunk: &Chunk) -> ChunkMesh {
+        let in_chunk = |i: i32| (0..CHUNK_SIZE).contains(&i);
+        ChunkMesh::greedy(chunk, |position, _, offset| {
+            let neighbour = position + offset;
+            [neighbour.x, neighbour.y, neighbour.z]
+                .into_iter()
+                .all(in_chunk)
+                && chunk.get_voxel(neighbour) != AIR
+        })
+    }
+
+    // Face and block of every quad
+    fn quads(mesh: &ChunkMesh) -> Vec<(u32, BlockId)> {
+        assert_eq!(mesh.indices.len(), mesh.vertices.len() / 4 * 6);
+        mesh.vertices
+            .chunks(4)
+            .map(|quad| (quad[0].face, quad[0].block as BlockId))
+            .collect()
+    }
+
+    #[test]
+    fn merges_a_slab_into_one_quad_per_face() {
+        let chunk = chunk(|p| (p.y == 3 && p.x < 8 && p.z < 8).then_some(1));
+        let mut faces = quads(&mesh(&chunk))
+            .into_iter()
+            .map(|(face, _)| face)
+            .collect::<Vec<_>>();
+        faces.sort();
+        assert_eq!(faces, (0..6).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn keeps_different_blocks_apart() {
+        let chunk =
+            chunk(|p| (p.y == 0 && p.x < 8 && p.z < 8).then_some(if p.x < 4 { 1 } else { 2 }));
+        let quads = quads(&mesh(&chunk));
+
+        let mut up = quads
+            .iter()
+            .filter(|(face, _)| *face == face::FACE_UP)
+            .map(|(_, block)| *block)
+            .collect::<Vec<_>>();
+        up.sort();
+        assert_eq!(up, [1, 2]);
+        // Up, down, front and back are split in two, left and right aren't
+        assert_eq!(quads.len(), 10);
+    }
+}
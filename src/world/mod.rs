@@ -1,3 +1,11 @@
 //pub mod cubes;
+pub mod block;
+pub mod chunk;
+pub mod cursor;
+pub mod export;
+pub mod mesher;
+pub mod region;
+pub mod terrain;
+pub mod vox;
 pub mod voxel;
 pub mod voxel_manager;
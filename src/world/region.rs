@@ -0,0 +1,409 @@
+// Region files group REGION_SIZE³ chunks:
+//
+//   header      magic "SHRG", version u16, reserved u16, chunk count u32
+//   block names count u16, then (length u8, utf-8 name) per saved block id
+//   offsets     per chunk: position 3 x i32, offset u32, length u32
+//   chunks      palette length u16, palette u16 * n,
+//               run count u32, then (palette index u16, length u16) per run
+//
+// All integers are little endian. Block ids are stored by name so saves
+// survive changes to the registration order of the block registry.
+
+use std::io::{Read, Write};
+
+use super::block::{BlockId, BlockRegistry};
+use super::chunk::{Chunk, CHUNK_VOLUME};
+use super::cursor::{invalid_data, Cursor};
+
+pub const REGION_VERSION: u16 = 1;
+pub const REGION_SIZE: i32 = 8;
+pub const REGION_EXTENSION: &str = "region";
+
+const MAGIC: &[u8; 4] = b"SHRG";
+const OFFSET_ENTRY_SIZE: usize = 20;
+
+pub fn region_position(chunk_position: cgmath::Vector3<i32>) -> cgmath::Vector3<i32> {
+    cgmath::vec3(
+        chunk_position.x.div_euclid(REGION_SIZE),
+        chunk_position.y.div_euclid(REGION_SIZE),
+        chunk_position.z.div_euclid(REGION_SIZE),
+    )
+}
+
+pub fn file_name(region_position: cgmath::Vector3<i32>) -> String {
+    format!(
+        "r.{}.{}.{}.{}",
+        region_position.x, region_position.y, region_position.z, REGION_EXTENSION
+    )
+}
+
+pub fn write_region<W: Write>(
+    writer: &mut W,
+    chunks: &[&Chunk],
+    registry: &BlockRegistry,
+) -> std::io::Result<()> {
+    // Saved ids index into the name table, built from the blocks actually used
+    let mut names = Vec::<BlockId>::new();
+    let mut saved_ids = std::collections::HashMap::<BlockId, u16>::new();
+    let encoded = chunks
+        .iter()
+        .map(|chunk| {
+            let blocks = chunk
+                .blocks()
+                .iter()
+                .map(|block| {
+                    *saved_ids.entry(*block).or_insert_with(|| {
+                        names.push(*block);
+                        (names.len() - 1) as u16
+                    })
+                })
+                .collect::<Vec<_>>();
+            encode_chunk(&blocks)
+        })
+        .collect::<Vec<_>>();
+
+    let mut header = Vec::new();
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&REGION_VERSION.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+
+    header.extend_from_slice(&(names.len() as u16).to_le_bytes());
+    for block in names {
+        let name = registry.get(block).name.as_bytes();
+        // Lengths are saved as a byte, longer names wouldn't read back
+        if name.len() > u8::MAX as usize {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Block name {} is too long", registry.get(block).name),
+            ));
+        }
+        header.push(name.len() as u8);
+        header.extend_from_slice(name);
+    }
+
+    let mut offset = header.len() + chunks.len() * OFFSET_ENTRY_SIZE;
+    for (chunk, data) in chunks.iter().zip(encoded.iter()) {
+        header.extend_from_slice(&chunk.position.x.to_le_bytes());
+        header.extend_from_slice(&chunk.position.y.to_le_bytes());
+        header.extend_from_slice(&chunk.position.z.to_le_bytes());
+        header.extend_from_slice(&(offset as u32).to_le_bytes());
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        offset += data.len();
+    }
+
+    writer.write_all(&header)?;
+    for data in encoded {
+        writer.write_all(&data)?;
+    }
+
+    Ok(())
+}
+
+pub fn read_region<R: Read>(
+    reader: &mut R,
+    registry: &mut BlockRegistry,
+) -> std::io::Result<Vec<Chunk>> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let mut cursor = Cursor::new(&data);
+
+    if cursor.bytes(MAGIC.len())? != MAGIC {
+        return Err(invalid_data("Not a region file".to_string()));
+    }
+    let version = cursor.u16()?;
+    if version != REGION_VERSION {
+        return Err(invalid_data(format!(
+            "Unsupported region version {}, expected {}",
+            version, REGION_VERSION
+        )));
+    }
+    cursor.u16()?;
+    let chunk_count = cursor.u32()? as usize;
+
+    let name_count = cursor.u16()? as usize;
+    let mut ids = Vec::with_capacity(name_count);
+    for _ in 0..name_count {
+        let length = cursor.bytes(1)?[0] as usize;
+        let name = String::from_utf8_lossy(cursor.bytes(length)?).into_owned();
+        ids.push(
+            registry
+                .resolve(&name)
+                .ok_or_else(|| invalid_data(format!("Could not resolve block {}", name)))?,
+        );
+    }
+
+    // Checked before allocating, the count comes straight from the file
+    if chunk_count
+        .checked_mul(OFFSET_ENTRY_SIZE)
+        .is_none_or(|size| size > cursor.remaining())
+    {
+        return Err(invalid_data(format!(
+            "Region has {} chunks but no room for them",
+            chunk_count
+        )));
+    }
+
+    let mut chunks = Vec::with_capacity(chunk_count);
+    for _ in 0..chunk_count {
+        let position = cgmath::vec3(cursor.i32()?, cursor.i32()?, cursor.i32()?);
+        let offset = cursor.u32()? as usize;
+        let length = cursor.u32()? as usize;
+
+        let chunk_data = data
+            .get(offset..offset + length)
+            .ok_or_else(|| invalid_data(format!("Chunk {:?} out of bounds", position)))?;
+        let blocks = decode_chunk(chunk_data)?
+            .into_iter()
+            .map(|saved| {
+                ids.get(saved as usize)
+                    .copied()
+                    .ok_or_else(|| invalid_data(format!("Unknown saved block id {}", saved)))
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        chunks.push(Chunk::from_blocks(position, blocks));
+    }
+
+    Ok(chunks)
+}
+
+fn encode_chunk(blocks: &[u16]) -> Vec<u8> {
+    let mut palette = Vec::<u16>::new();
+    let mut runs = Vec::<(u16, u16)>::new();
+
+    for block in blocks {
+        let index = match palette.iter().position(|p| p == block) {
+            Some(index) => index,
+            None => {
+                palette.push(*block);
+                palette.len() - 1
+            }
+        } as u16;
+
+        match runs.last_mut() {
+            Some((run_index, length)) if *run_index == index && *length < u16::MAX => *length += 1,
+            _ => runs.push((index, 1)),
+        }
+    }
+
+    let mut data = Vec::with_capacity(2 + palette.len() * 2 + 4 + runs.len() * 4);
+    data.extend_from_slice(&(palette.len() as u16).to_le_bytes());
+    for block in palette {
+        data.extend_from_slice(&block.to_le_bytes());
+    }
+    data.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+    for (index, length) in runs {
+        data.extend_from_slice(&index.to_le_bytes());
+        data.extend_from_slice(&length.to_le_bytes());
+    }
+    data
+}
+
+fn decode_chunk(data: &[u8]) -> std::io::Result<Vec<u16>> {
+    let mut cursor = Cursor::new(data);
+
+    let palette_length = cursor.u16()? as usize;
+    let palette = (0..palette_length)
+        .map(|_| cursor.u16())
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    // Counts come from the file, so they are checked before anything grows
+    let run_count = cursor.u32()? as usize;
+    if run_count > CHUNK_VOLUME {
+        return Err(invalid_data(format!("Chunk has {} runs", run_count)));
+    }
+    let mut blocks = Vec::with_capacity(CHUNK_VOLUME);
+    for _ in 0..run_count {
+        let index = cursor.u16()? as usize;
+        let length = cursor.u16()? as usize;
+        let block = *palette
+            .get(index)
+            .ok_or_else(|| invalid_data(format!("Palette index {} out of range", index)))?;
+        if blocks.len() + length > CHUNK_VOLUME {
+            return Err(invalid_data(format!(
+                "Chunk has more than {} blocks",
+                CHUNK_VOLUME
+            )));
+        }
+        blocks.extend(std::iter::repeat_n(block, length));
+    }
+
+    if blocks.len() != CHUNK_VOLUME {
+        return Err(invalid_data(format!(
+            "Chunk has {} blocks, expected {}",
+            blocks.len(),
+            CHUNK_VOLUME
+        )));
+    }
+
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_chunks(registry: &BlockRegistry) -> Vec<Chunk> {
+        let stone = registry.get_id("stone").unwrap();
+        let water = registry.get_id("water").unwrap();
+
+        let mut layered = Chunk::new(cgmath::vec3(0, 0, 0));
+        for x in 0..32 {
+            for z in 0..32 {
+                for y in 0..8 {
+                    layered.set_voxel(cgmath::vec3(x, y, z), stone);
+                }
+                layered.set_voxel(cgmath::vec3(x, 8, z), water);
+            }
+        }
+
+        // Alternating blocks are the worst case for run length encoding
+        let mut noisy = Chunk::new(cgmath::vec3(-1, 2, 7));
+        let blocks = (0..CHUNK_VOLUME)
+            .map(|i| ((i * 7919) % 5) as BlockId)
+            .collect::<Vec<_>>();
+        noisy.set_blocks(blocks);
+
+        vec![layered, noisy, Chunk::new(cgmath::vec3(3, -4, 5))]
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut registry = BlockRegistry::new();
+        let chunks = test_chunks(&registry);
+
+        let mut data = Vec::new();
+        write_region(&mut data, &chunks.iter().collect::<Vec<_>>(), &registry).unwrap();
+        let loaded = read_region(&mut data.as_slice(), &mut registry).unwrap();
+
+        assert_eq!(loaded.len(), chunks.len());
+        for (chunk, loaded) in chunks.iter().zip(loaded.iter()) {
+            assert_eq!(chunk.position, loaded.position);
+            assert_eq!(chunk.blocks(), loaded.blocks());
+            assert_eq!(chunk.is_empty(), loaded.is_empty());
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_blocks() {
+        let mut registry = BlockRegistry::new();
+        let marble = registry.register(super::super::block::Block::new("marble", [1.0; 4]));
+
+        let mut chunk = Chunk::new(cgmath::vec3(0, 0, 0));
+        chunk.set_voxel(cgmath::vec3(1, 2, 3), marble);
+
+        let mut data = Vec::new();
+        write_region(&mut data, &[&chunk], &registry).unwrap();
+
+        match read_region(&mut data.as_slice(), &mut BlockRegistry::new()) {
+            Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+            Ok(_) => panic!("Loaded a region with an unknown block"),
+        }
+    }
+
+    #[test]
+    fn rejects_long_block_names() {
+        let mut registry = BlockRegistry::new();
+        let name = "x".repeat(256);
+        let long = registry.register(super::super::block::Block::new(&name, [1.0; 4]));
+
+        let mut chunk = Chunk::new(cgmath::vec3(0, 0, 0));
+        chunk.set_voxel(cgmath::vec3(1, 2, 3), long);
+
+        match write_region(&mut Vec::new(), &[&chunk], &registry) {
+            Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput),
+            Ok(_) => panic!("Saved a block name that can't be read back"),
+        }
+    }
+
+    #[test]
+    fn recreates_color_blocks() {
+        let mut registry = BlockRegistry::new();
+        let color = registry.register_color([200, 100, 50, 255]);
+
+        let mut chunk = Chunk::new(cgmath::vec3(0, 0, 0));
+        chunk.set_voxel(cgmath::vec3(1, 2, 3), color);
+
+        let mut data = Vec::new();
+        write_region(&mut data, &[&chunk], &registry).unwrap();
+
+        let mut loaded_registry = BlockRegistry::new();
+        let loaded = read_region(&mut data.as_slice(), &mut loaded_registry).unwrap();
+        let block = loaded[0].get_voxel(cgmath::vec3(1, 2, 3));
+        assert_eq!(loaded_registry.get(block).name, registry.get(color).name);
+    }
+
+    #[test]
+    fn layered_chunk_compresses() {
+        let registry = BlockRegistry::new();
+        let chunks = test_chunks(&registry);
+
+        let mut data = Vec::new();
+        write_region(&mut data, &[&chunks[0]], &registry).unwrap();
+
+        assert!(data.len() < 256, "{} bytes", data.len());
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut registry = BlockRegistry::new();
+        let chunks = test_chunks(&registry);
+
+        let mut data = Vec::new();
+        write_region(&mut data, &[&chunks[0]], &registry).unwrap();
+        data[4..6].copy_from_slice(&(REGION_VERSION + 1).to_le_bytes());
+
+        match read_region(&mut data.as_slice(), &mut registry) {
+            Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+            Ok(_) => panic!("Loaded a region with a different version"),
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_runs() {
+        let run = |index: u16, length: u16| [index.to_le_bytes(), length.to_le_bytes()].concat();
+
+        // One palette entry, then more blocks than a chunk holds
+        let mut data = [1u16.to_le_bytes(), 0u16.to_le_bytes()].concat();
+        data.extend_from_slice(&3u32.to_le_bytes());
+        for _ in 0..3 {
+            data.extend(run(0, u16::MAX));
+        }
+        // Rejected at the run that overflows, not after expanding all of them
+        let error = decode_chunk(&data).unwrap_err();
+        assert!(error.to_string().contains("more than"), "{}", error);
+
+        let mut data = [1u16.to_le_bytes(), 0u16.to_le_bytes()].concat();
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        let error = decode_chunk(&data).unwrap_err();
+        assert!(error.to_string().contains("runs"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_huge_chunk_counts() {
+        let mut registry = BlockRegistry::new();
+        let chunks = test_chunks(&registry);
+
+        let mut data = Vec::new();
+        write_region(&mut data, &[&chunks[0]], &registry).unwrap();
+        data[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        match read_region(&mut data.as_slice(), &mut registry) {
+            Err(e) => assert!(e.to_string().contains("no room"), "{}", e),
+            Ok(_) => panic!("Loaded a region with more chunks than data"),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let mut registry = BlockRegistry::new();
+        let chunks = test_chunks(&registry);
+
+        let mut data = Vec::new();
+        write_region(&mut data, &[&chunks[1]], &registry).unwrap();
+        data.truncate(data.len() - 3);
+
+        assert!(read_region(&mut data.as_slice(), &mut registry).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Biome {
+    Plains,
+    Desert,
+    Forest,
+    Tundra,
+    Mountains,
+}
+
+pub const BIOMES: [Biome; 5] = [
+    Biome::Plains,
+    Biome::Desert,
+    Biome::Forest,
+    Biome::Tundra,
+    Biome::Mountains,
+];
+
+impl Biome {
+    // Position of the biome in (temperature, humidity) space, both in [-1, 1]
+    fn climate(&self) -> (f32, f32) {
+        match self {
+            Biome::Plains => (0.1, -0.1),
+            Biome::Desert => (0.7, -0.6),
+            Biome::Forest => (0.2, 0.6),
+            Biome::Tundra => (-0.7, -0.3),
+            Biome::Mountains => (-0.5, 0.5),
+        }
+    }
+
+    // Maps the height noise in [-1, 1] to a height offset from the base height
+    pub fn height(&self, noise: f32, amplitude: f32) -> f32 {
+        match self {
+            Biome::Plains => 1.0 + noise * amplitude * 0.4,
+            Biome::Desert => noise * amplitude * 0.3,
+            Biome::Forest => 2.0 + noise * amplitude * 0.7,
+            Biome::Tundra => 1.0 + noise * amplitude * 0.5,
+            // Ridged noise gives sharp peaks instead of rolling hills
+            Biome::Mountains => 4.0 + (1.0 - noise.abs() * 2.0).max(0.0) * amplitude * 1.5,
+        }
+    }
+
+    pub fn surface_block(&self) -> &'static str {
+        match self {
+            Biome::Plains | Biome::Forest => "grass",
+            Biome::Desert => "sand",
+            Biome::Tundra => "snow",
+            Biome::Mountains => "stone",
+        }
+    }
+
+    pub fn subsurface_block(&self) -> &'static str {
+        match self {
+            Biome::Plains | Biome::Forest | Biome::Tundra => "dirt",
+            Biome::Desert => "sand",
+            Biome::Mountains => "stone",
+        }
+    }
+
+    pub fn weights(temperature: f32, humidity: f32) -> [f32; BIOMES.len()] {
+        let mut weights = [0.0; BIOMES.len()];
+        let mut total = 0.0;
+
+        for (weight, biome) in weights.iter_mut().zip(BIOMES) {
+            let (t, h) = biome.climate();
+            let distance = (temperature - t).powi(2) + (humidity - h).powi(2);
+            *weight = (-distance / BLEND_WIDTH).exp();
+            total += *weight;
+        }
+
+        for weight in weights.iter_mut() {
+            *weight /= total;
+        }
+        weights
+    }
+}
+
+// Smaller values give sharper borders between biomes
+const BLEND_WIDTH: f32 = 0.05;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dominant(temperature: f32, humidity: f32) -> Biome {
+        let weights = Biome::weights(temperature, humidity);
+        let index = (0..BIOMES.len())
+            .max_by(|a, b| weights[*a].total_cmp(&weights[*b]))
+            .unwrap();
+        BIOMES[index]
+    }
+
+    #[test]
+    fn weights_sum_to_one() {
+        for t in -10..=10 {
+            for h in -10..=10 {
+                let weights = Biome::weights(t as f32 / 10.0, h as f32 / 10.0);
+                assert!((weights.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+                assert!(weights.iter().all(|w| (0.0..=1.0).contains(w)));
+            }
+        }
+    }
+
+    #[test]
+    fn extreme_climates_pick_their_biome() {
+        assert_eq!(dominant(1.0, -1.0), Biome::Desert);
+        assert_eq!(dominant(1.0, 1.0), Biome::Forest);
+        assert_eq!(dominant(-1.0, -1.0), Biome::Tundra);
+        assert_eq!(dominant(-1.0, 1.0), Biome::Mountains);
+        assert_eq!(dominant(0.0, 0.0), Biome::Plains);
+    }
+}
@@ -0,0 +1,178 @@
+use super::noise;
+
+#[derive(Copy, Clone, Debug)]
+pub struct CaveConfig {
+    pub enabled: bool,
+    // Large open caverns where the noise is above the threshold
+    pub cheese_threshold: f32,
+    pub cheese_octaves: noise::Octaves,
+    // Tunnels along the intersection of two noise zero surfaces
+    pub worm_radius: f32,
+    pub worm_octaves: noise::Octaves,
+    pub overhang_strength: f32,
+    pub overhang_octaves: noise::Octaves,
+    // Solid layers kept above the bottom of the world and below the surface
+    pub floor: i32,
+    pub crust: i32,
+}
+
+impl Default for CaveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            cheese_threshold: 0.28,
+            cheese_octaves: noise::Octaves {
+                count: 2,
+                frequency: 1.0 / 24.0,
+                persistence: 0.5,
+                lacunarity: 2.0,
+            },
+            worm_radius: 0.06,
+            worm_octaves: noise::Octaves {
+                count: 2,
+                frequency: 1.0 / 32.0,
+                persistence: 0.5,
+                lacunarity: 2.0,
+            },
+            overhang_strength: 4.0,
+            overhang_octaves: noise::Octaves {
+                count: 2,
+                frequency: 1.0 / 16.0,
+                persistence: 0.5,
+                lacunarity: 2.0,
+            },
+            floor: 2,
+            crust: 4,
+        }
+    }
+}
+
+pub struct Caves {
+    pub config: CaveConfig,
+    cheese_noise: noise::Noise,
+    worm_noise: [noise::Noise; 2],
+    overhang_noise: noise::Noise,
+}
+
+impl Caves {
+    pub fn new(seed: u64, kind: noise::NoiseKind, config: CaveConfig) -> Self {
+        Self {
+            config,
+            cheese_noise: noise::Noise::new(seed ^ 0x0C4E_E5E0, kind),
+            worm_noise: [
+                noise::Noise::new(seed ^ 0x3A17_90D1, kind),
+                noise::Noise::new(seed ^ 0x5B2C_46F3, kind),
+            ],
+            overhang_noise: noise::Noise::new(seed ^ 0x1F0E_77A9, kind),
+        }
+    }
+
+    // Highest y the density function can make solid above the heightmap
+    pub fn overhang_range(&self, strength: f32) -> i32 {
+        if self.config.enabled {
+            (self.config.overhang_strength * strength).ceil() as i32
+        } else {
+            0
+        }
+    }
+
+    // `strength` scales the overhangs, so steep biomes can get more of them
+    pub fn is_solid(
+        &self,
+        position: cgmath::Vector3<i32>,
+        height: i32,
+        bottom: i32,
+        strength: f32,
+    ) -> bool {
+        if !self.config.enabled {
+            return position.y <= height;
+        }
+
+        let (x, y, z) = (position.x as f32, position.y as f32, position.z as f32);
+
+        let overhang = self
+            .overhang_noise
+            .fbm3(x, y, z, &self.config.overhang_octaves);
+        let density =
+            (height - position.y) as f32 + overhang * self.config.overhang_strength * strength;
+        if density <= 0.0 {
+            return false;
+        }
+
+        if position.y < bottom + self.config.floor {
+            return true;
+        }
+
+        let worm = self.worm_noise[0]
+            .fbm3(x, y * 2.0, z, &self.config.worm_octaves)
+            .powi(2)
+            + self.worm_noise[1]
+                .fbm3(x, y * 2.0, z, &self.config.worm_octaves)
+                .powi(2);
+        if worm < self.config.worm_radius.powi(2) {
+            return false;
+        }
+
+        // Caverns stay under a crust so they don't swallow the surface
+        if position.y < height - self.config.crust {
+            let cheese = self
+                .cheese_noise
+                .fbm3(x, y * 1.5, z, &self.config.cheese_octaves);
+            if cheese > self.config.cheese_threshold {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEIGHT: i32 = 20;
+    const BOTTOM: i32 = -40;
+
+    fn solid(caves: &Caves) -> Vec<bool> {
+        let mut solid = Vec::new();
+        for z in 0..16 {
+            for x in 0..16 {
+                for y in BOTTOM..HEIGHT + 8 {
+                    solid.push(caves.is_solid(cgmath::vec3(x, y, z), HEIGHT, BOTTOM, 1.0));
+                }
+            }
+        }
+        solid
+    }
+
+    #[test]
+    fn caverns_stay_under_the_crust() {
+        // Only the caverns carve
+        let config = CaveConfig {
+            worm_radius: 0.0,
+            overhang_strength: 0.0,
+            ..CaveConfig::default()
+        };
+        let caves = Caves::new(3, noise::NoiseKind::Perlin, config);
+
+        let mut carved = false;
+        for z in 0..16 {
+            for x in 0..16 {
+                let solid = |y| caves.is_solid(cgmath::vec3(x, y, z), HEIGHT, BOTTOM, 1.0);
+                assert!((HEIGHT - config.crust..HEIGHT).all(solid));
+                assert!((BOTTOM..BOTTOM + config.floor).all(solid));
+                assert!(!(HEIGHT..HEIGHT + 8).any(solid));
+                carved |= !(BOTTOM..HEIGHT - config.crust).all(solid);
+            }
+        }
+        assert!(carved);
+    }
+
+    #[test]
+    fn same_seed_carves_the_same_caves() {
+        let caves = |seed| Caves::new(seed, noise::NoiseKind::Perlin, CaveConfig::default());
+        assert_eq!(solid(&caves(5)), solid(&caves(5)));
+        assert_ne!(solid(&caves(5)), solid(&caves(6)));
+    }
+}
@@ -0,0 +1,257 @@
+pub mod biome;
+pub mod caves;
+pub mod noise;
+
+use super::block::BlockId;
+
+#[derive(Copy, Clone, Debug)]
+pub struct TerrainConfig {
+    pub seed: u64,
+    pub size: i32,
+    pub noise: noise::NoiseKind,
+    pub octaves: noise::Octaves,
+    pub climate_octaves: noise::Octaves,
+    pub amplitude: f32,
+    pub base_height: i32,
+    pub sea_level: i32,
+    pub bottom: i32,
+    pub soil_depth: i32,
+    pub snow_line: i32,
+    pub caves: caves::CaveConfig,
+}
+
+impl TerrainConfig {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            size: 64,
+            noise: noise::NoiseKind::Perlin,
+            octaves: noise::Octaves {
+                count: 4,
+                frequency: 1.0 / 48.0,
+                persistence: 0.5,
+                lacunarity: 2.0,
+            },
+            climate_octaves: noise::Octaves {
+                count: 2,
+                frequency: 1.0 / 96.0,
+                persistence: 0.5,
+                lacunarity: 2.0,
+            },
+            amplitude: 20.0,
+            base_height: 0,
+            sea_level: -3,
+            bottom: -16,
+            soil_depth: 3,
+            snow_line: 18,
+            caves: caves::CaveConfig::default(),
+        }
+    }
+}
+
+struct Palette {
+    surface: [BlockId; biome::BIOMES.len()],
+    subsurface: [BlockId; biome::BIOMES.len()],
+    stone: BlockId,
+    sand: BlockId,
+    snow: BlockId,
+    water: BlockId,
+}
+
+impl Palette {
+    fn new(registry: &super::block::BlockRegistry) -> Self {
+        let block = |name: &str| registry.get_id(name).unwrap();
+        Self {
+            surface: biome::BIOMES.map(|biome| block(biome.surface_block())),
+            subsurface: biome::BIOMES.map(|biome| block(biome.subsurface_block())),
+            stone: block("stone"),
+            sand: block("sand"),
+            snow: block("snow"),
+            water: block("water"),
+        }
+    }
+}
+
+pub struct TerrainGenerator {
+    pub config: TerrainConfig,
+    height_noise: noise::Noise,
+    temperature_noise: noise::Noise,
+    humidity_noise: noise::Noise,
+    caves: caves::Caves,
+}
+
+impl TerrainGenerator {
+    pub fn new(config: TerrainConfig) -> Self {
+        Self {
+            height_noise: noise::Noise::new(config.seed, config.noise),
+            temperature_noise: noise::Noise::new(config.seed ^ 0x7E3F_A1C2, config.noise),
+            humidity_noise: noise::Noise::new(config.seed ^ 0x4D59_6B8E, config.noise),
+            caves: caves::Caves::new(config.seed, config.noise, config.caves),
+            config,
+        }
+    }
+
+    // Climate fields stretched to roughly cover [-1, 1]
+    fn climate(&self, x: i32, z: i32) -> (f32, f32) {
+        let octaves = &self.config.climate_octaves;
+        let temperature = self.temperature_noise.fbm2(x as f32, z as f32, octaves);
+        let humidity = self.humidity_noise.fbm2(x as f32, z as f32, octaves);
+        (
+            (temperature * 2.5).clamp(-1.0, 1.0),
+            (humidity * 2.5).clamp(-1.0, 1.0),
+        )
+    }
+
+    pub fn biome(&self, x: i32, z: i32) -> biome::Biome {
+        let (temperature, humidity) = self.climate(x, z);
+        let weights = biome::Biome::weights(temperature, humidity);
+
+        let mut dominant = 0;
+        for (i, weight) in weights.iter().enumerate() {
+            if *weight > weights[dominant] {
+                dominant = i;
+            }
+        }
+        biome::BIOMES[dominant]
+    }
+
+    pub fn height(&self, x: i32, z: i32) -> i32 {
+        let n = self
+            .height_noise
+            .fbm2(x as f32, z as f32, &self.config.octaves);
+        let (temperature, humidity) = self.climate(x, z);
+
+        // Blend the height curves of all biomes so borders don't form cliffs
+        let height = biome::Biome::weights(temperature, humidity)
+            .iter()
+            .zip(biome::BIOMES)
+            .map(|(weight, biome)| weight * biome.height(n, self.config.amplitude))
+            .sum::<f32>();
+
+        self.config.base_height + height.round() as i32
+    }
+
+    pub fn generate(&self, voxel_manager: &mut super::voxel_manager::VoxelManger) {
+        let palette = Palette::new(&voxel_manager.block_registry);
+
+        let half = self.config.size / 2;
+        for z in -half..self.config.size - half {
+            for x in -half..self.config.size - half {
+                let height = self.height(x, z);
+                let biome = self.biome(x, z);
+                let (temperature, humidity) = self.climate(x, z);
+                let mountains = biome::BIOMES
+                    .iter()
+                    .position(|b| *b == biome::Biome::Mountains)
+                    .unwrap();
+                let strength = 1.0 + 3.0 * biome::Biome::weights(temperature, humidity)[mountains];
+
+                let top = height.max(self.config.sea_level) + self.caves.overhang_range(strength);
+                let solid = |y| {
+                    self.caves
+                        .is_solid(cgmath::vec3(x, y, z), height, self.config.bottom, strength)
+                };
+                for (y, block) in self.column(&palette, biome, height, top, solid) {
+                    voxel_manager.set_voxel(cgmath::vec3(x, y, z), block);
+                }
+            }
+        }
+    }
+
+    // Walks down the column from `top`, every solid voxel below air gets the
+    // surface block, so ground under an overhang gets its soil too
+    fn column<F: Fn(i32) -> bool>(
+        &self,
+        palette: &Palette,
+        biome: biome::Biome,
+        height: i32,
+        top: i32,
+        solid: F,
+    ) -> Vec<(i32, BlockId)> {
+        let mut blocks = Vec::new();
+        let mut surface = None;
+        for y in (self.config.bottom..=top).rev() {
+            if solid(y) {
+                let surface = *surface.get_or_insert(y);
+                blocks.push((y, self.column_block(palette, biome, surface, y)));
+            } else {
+                surface = None;
+                if y <= self.config.sea_level && y > height {
+                    blocks.push((y, palette.water));
+                }
+            }
+        }
+        blocks
+    }
+
+    fn column_block(&self, palette: &Palette, biome: biome::Biome, height: i32, y: i32) -> BlockId {
+        let beach = height <= self.config.sea_level + 1;
+        let index = biome::BIOMES.iter().position(|b| *b == biome).unwrap();
+
+        if y == height {
+            if height >= self.config.snow_line {
+                palette.snow
+            } else if beach {
+                palette.sand
+            } else {
+                palette.surface[index]
+            }
+        } else if y > height - self.config.soil_depth {
+            if beach {
+                palette.sand
+            } else {
+                palette.subsurface[index]
+            }
+        } else {
+            palette.stone
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heights(seed: u64) -> Vec<i32> {
+        let generator = TerrainGenerator::new(TerrainConfig::new(seed));
+        (-32..32)
+            .flat_map(|z| (-32..32).map(move |x| (x, z)))
+            .map(|(x, z)| generator.height(x, z))
+            .collect()
+    }
+
+    #[test]
+    fn ground_under_an_overhang_gets_its_soil() {
+        let registry = crate::world::block::BlockRegistry::new();
+        let block = |name| registry.get_id(name).unwrap();
+        let generator = TerrainGenerator::new(TerrainConfig::new(1));
+
+        // A ledge from 15 to 17 above the ground at 10
+        let column = generator.column(
+            &Palette::new(&registry),
+            biome::Biome::Plains,
+            10,
+            20,
+            |y| y <= 10 || (15..=17).contains(&y),
+        );
+        let at = |y| {
+            column
+                .iter()
+                .find(|(b, _)| *b == y)
+                .map(|(_, block)| *block)
+        };
+
+        assert_eq!(at(17), Some(block("grass")));
+        assert_eq!(at(15), Some(block("dirt")));
+        assert_eq!(at(12), None);
+        assert_eq!(at(10), Some(block("grass")));
+        assert_eq!(at(8), Some(block("dirt")));
+        assert_eq!(at(7), Some(block("stone")));
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_heights() {
+        assert_eq!(heights(7), heights(7));
+        assert_ne!(heights(7), heights(8));
+    }
+}
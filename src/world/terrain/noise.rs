@@ -0,0 +1,180 @@
+// SplitMix64, small and good enough to shuffle permutation tables from a seed
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NoiseKind {
+    Value,
+    Perlin,
+}
+
+pub struct Noise {
+    kind: NoiseKind,
+    permutation: Vec<usize>,
+    values: Vec<f32>,
+}
+
+impl Noise {
+    pub fn new(seed: u64, kind: NoiseKind) -> Self {
+        let mut rng = Rng::new(seed);
+
+        let mut permutation = (0..256).collect::<Vec<usize>>();
+        for i in (1..permutation.len()).rev() {
+            let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+            permutation.swap(i, j);
+        }
+        permutation.extend_from_within(..);
+
+        let values = (0..256).map(|_| rng.next_f32() * 2.0 - 1.0).collect();
+
+        Self {
+            kind,
+            permutation,
+            values,
+        }
+    }
+
+    fn hash(&self, x: i32, y: i32, z: i32) -> usize {
+        let p = &self.permutation;
+        p[p[p[(x & 255) as usize] + (y & 255) as usize] + (z & 255) as usize]
+    }
+
+    // Returns a value in [-1, 1]
+    pub fn get2(&self, x: f32, y: f32) -> f32 {
+        self.get3(x, y, 0.0)
+    }
+
+    // Returns a value in [-1, 1]
+    pub fn get3(&self, x: f32, y: f32, z: f32) -> f32 {
+        let (xi, yi, zi) = (x.floor() as i32, y.floor() as i32, z.floor() as i32);
+        let (xf, yf, zf) = (x - xi as f32, y - yi as f32, z - zi as f32);
+        let (u, v, w) = (fade(xf), fade(yf), fade(zf));
+
+        let corner = |dx: i32, dy: i32, dz: i32| {
+            let hash = self.hash(xi + dx, yi + dy, zi + dz);
+            match self.kind {
+                NoiseKind::Value => self.values[hash],
+                NoiseKind::Perlin => gradient(hash, xf - dx as f32, yf - dy as f32, zf - dz as f32),
+            }
+        };
+
+        let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), u);
+        let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), u);
+        let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), u);
+        let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), u);
+
+        lerp(lerp(x00, x10, v), lerp(x01, x11, v), w).clamp(-1.0, 1.0)
+    }
+
+    // Fractal sum of octaves, normalised back to [-1, 1]
+    pub fn fbm2(&self, x: f32, y: f32, octaves: &Octaves) -> f32 {
+        octaves.sum(|frequency| self.get2(x * frequency, y * frequency))
+    }
+
+    pub fn fbm3(&self, x: f32, y: f32, z: f32, octaves: &Octaves) -> f32 {
+        octaves.sum(|frequency| self.get3(x * frequency, y * frequency, z * frequency))
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Octaves {
+    pub count: u32,
+    pub frequency: f32,
+    pub persistence: f32,
+    pub lacunarity: f32,
+}
+
+impl Octaves {
+    fn sum<F: Fn(f32) -> f32>(&self, sample: F) -> f32 {
+        let mut total = 0.0;
+        let mut max = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = self.frequency;
+
+        for _ in 0..self.count {
+            total += sample(frequency) * amplitude;
+            max += amplitude;
+            amplitude *= self.persistence;
+            frequency *= self.lacunarity;
+        }
+
+        if max > 0.0 {
+            total / max
+        } else {
+            0.0
+        }
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn gradient(hash: usize, x: f32, y: f32, z: f32) -> f32 {
+    // The 12 cube edge directions of improved Perlin noise
+    match hash & 15 {
+        0 | 12 => x + y,
+        1 | 14 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 | 13 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OCTAVES: Octaves = Octaves {
+        count: 3,
+        frequency: 1.0 / 16.0,
+        persistence: 0.5,
+        lacunarity: 2.0,
+    };
+
+    fn samples(seed: u64, kind: NoiseKind) -> Vec<f32> {
+        let noise = Noise::new(seed, kind);
+        (0..64)
+            .map(|i| noise.fbm3(i as f32 * 3.7, i as f32 * 1.3, i as f32 * -2.1, &OCTAVES))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_noise() {
+        for kind in [NoiseKind::Value, NoiseKind::Perlin] {
+            assert_eq!(samples(42, kind), samples(42, kind));
+            assert_ne!(samples(42, kind), samples(43, kind));
+            assert!(samples(42, kind).iter().all(|n| (-1.0..=1.0).contains(n)));
+        }
+    }
+}
@@ -0,0 +1,728 @@
+// MagicaVoxel .vox files: https://github.com/ephtracy/voxel-model
+//
+// MagicaVoxel is z-up while the world is y-up, models are converted with
+// (x, y, z) -> (x, z, -y) when placed into the world.
+
+use std::io::{Read, Write};
+
+use super::cursor::{invalid_data, Cursor};
+
+const MAGIC: &[u8; 4] = b"VOX ";
+const VERSION: i32 = 150;
+
+// Voxel positions inside a model are stored as bytes
+pub const MAX_MODEL_SIZE: i32 = 256;
+
+// Rows of an integer rotation matrix, cgmath only multiplies float matrices
+pub type Rotation = [[i32; 3]; 3];
+
+pub const IDENTITY: Rotation = [[1, 0, 0], [0, 1, 0], [0, 0, 1]];
+
+pub fn rotate(rotation: &Rotation, v: cgmath::Vector3<i32>) -> cgmath::Vector3<i32> {
+    let row = |r: [i32; 3]| r[0] * v.x + r[1] * v.y + r[2] * v.z;
+    cgmath::vec3(row(rotation[0]), row(rotation[1]), row(rotation[2]))
+}
+
+fn compose(a: &Rotation, b: &Rotation) -> Rotation {
+    let mut m = [[0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+pub struct VoxModel {
+    pub size: cgmath::Vector3<i32>,
+    // Position inside the model and palette color index
+    pub voxels: Vec<([u8; 3], u8)>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct VoxInstance {
+    pub model: usize,
+    pub rotation: Rotation,
+    pub translation: cgmath::Vector3<i32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorMapping {
+    // Register a color block for every palette color used
+    Exact,
+    // Use the registered block with the closest color
+    Nearest,
+}
+
+pub struct VoxFile {
+    pub models: Vec<VoxModel>,
+    pub palette: [[u8; 4]; 256],
+    pub instances: Vec<VoxInstance>,
+}
+
+enum Node {
+    Transform {
+        child: i32,
+        rotation: Rotation,
+        translation: cgmath::Vector3<i32>,
+    },
+    Group {
+        children: Vec<i32>,
+    },
+    Shape {
+        models: Vec<usize>,
+    },
+}
+
+impl VoxFile {
+    pub fn open(path: &std::path::Path) -> std::io::Result<Self> {
+        Self::read(&mut std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let mut cursor = Cursor::new(&data);
+
+        if cursor.bytes(MAGIC.len())? != MAGIC {
+            return Err(invalid_data("Not a .vox file".to_string()));
+        }
+        cursor.i32()?;
+
+        let (id, content, children) = read_chunk(&mut cursor)?;
+        if id != b"MAIN" {
+            return Err(invalid_data("Missing MAIN chunk".to_string()));
+        }
+        let _ = content;
+
+        let mut models = Vec::new();
+        let mut palette = default_palette();
+        let mut nodes = std::collections::HashMap::new();
+        let mut size = None;
+
+        let mut cursor = Cursor::new(children);
+        while !cursor.is_empty() {
+            let (id, content, _) = read_chunk(&mut cursor)?;
+            let mut content = Cursor::new(content);
+
+            match id {
+                b"SIZE" => {
+                    size = Some(cgmath::vec3(content.i32()?, content.i32()?, content.i32()?));
+                }
+                b"XYZI" => {
+                    let size = size
+                        .take()
+                        .ok_or_else(|| invalid_data("XYZI chunk without SIZE".to_string()))?;
+                    let count = content.u32()? as usize;
+                    if count
+                        .checked_mul(4)
+                        .is_none_or(|bytes| bytes > content.remaining())
+                    {
+                        return Err(invalid_data(format!(
+                            "XYZI chunk has {} voxels but no room for them",
+                            count
+                        )));
+                    }
+                    let mut voxels = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        let v = content.bytes(4)?;
+                        voxels.push(([v[0], v[1], v[2]], v[3]));
+                    }
+                    models.push(VoxModel { size, voxels });
+                }
+                b"RGBA" => {
+                    // Color index i is stored at entry i - 1
+                    for i in 0..255 {
+                        palette[i + 1] = content.bytes(4)?.try_into().unwrap();
+                    }
+                }
+                b"nTRN" => {
+                    let id = content.i32()?;
+                    read_dict(&mut content)?;
+                    let child = content.i32()?;
+                    content.i32()?;
+                    content.i32()?;
+                    let frames = content.i32()?;
+
+                    let mut rotation = IDENTITY;
+                    let mut translation = cgmath::vec3(0, 0, 0);
+                    for frame in 0..frames {
+                        let attributes = read_dict(&mut content)?;
+                        if frame > 0 {
+                            continue;
+                        }
+                        if let Some(r) = attributes.get("_r") {
+                            let r = r
+                                .parse()
+                                .map_err(|_| invalid_data(format!("Invalid rotation {}", r)))?;
+                            rotation = decode_rotation(r)?;
+                        }
+                        if let Some(t) = attributes.get("_t") {
+                            let t = t
+                                .split_whitespace()
+                                .map(|v| v.parse::<i32>())
+                                .collect::<Result<Vec<_>, _>>()
+                                .map_err(|_| invalid_data(format!("Invalid translation {}", t)))?;
+                            if t.len() == 3 {
+                                translation = cgmath::vec3(t[0], t[1], t[2]);
+                            }
+                        }
+                    }
+
+                    nodes.insert(
+                        id,
+                        Node::Transform {
+                            child,
+                            rotation,
+                            translation,
+                        },
+                    );
+                }
+                b"nGRP" => {
+                    let id = content.i32()?;
+                    read_dict(&mut content)?;
+                    let count = content.i32()?;
+                    let children = (0..count)
+                        .map(|_| content.i32())
+                        .collect::<std::io::Result<Vec<_>>>()?;
+                    nodes.insert(id, Node::Group { children });
+                }
+                b"nSHP" => {
+                    let id = content.i32()?;
+                    read_dict(&mut content)?;
+                    let count = content.i32()?;
+                    let mut shape_models = Vec::new();
+                    for _ in 0..count {
+                        shape_models.push(content.i32()? as usize);
+                        read_dict(&mut content)?;
+                    }
+                    nodes.insert(
+                        id,
+                        Node::Shape {
+                            models: shape_models,
+                        },
+                    );
+                }
+                // Materials, layers, cameras and notes don't affect the voxels
+                _ => {}
+            }
+        }
+
+        let mut instances = Vec::new();
+        if nodes.is_empty() {
+            // Files written before the scene graph place every model at the origin
+            instances.extend((0..models.len()).map(|model| VoxInstance {
+                model,
+                rotation: IDENTITY,
+                translation: cgmath::vec3(0, 0, 0),
+            }));
+        } else {
+            collect_instances(
+                &nodes,
+                0,
+                IDENTITY,
+                cgmath::vec3(0, 0, 0),
+                &mut instances,
+                0,
+            )?;
+        }
+
+        if let Some(instance) = instances.iter().find(|i| i.model >= models.len()) {
+            return Err(invalid_data(format!("Unknown model {}", instance.model)));
+        }
+
+        Ok(Self {
+            models,
+            palette,
+            instances,
+        })
+    }
+
+    // World space (y-up) positions and colors of every voxel in the scene
+    pub fn voxels(&self) -> Vec<(cgmath::Vector3<i32>, [u8; 4])> {
+        let mut voxels = Vec::new();
+
+        for instance in self.instances.iter() {
+            let model = &self.models[instance.model];
+            let center = model.size / 2;
+
+            for (position, color) in model.voxels.iter() {
+                let local =
+                    cgmath::vec3(position[0] as i32, position[1] as i32, position[2] as i32);
+                let p = rotate(&instance.rotation, local - center) + instance.translation;
+                voxels.push((cgmath::vec3(p.x, p.z, -p.y), self.palette[*color as usize]));
+            }
+        }
+
+        voxels
+    }
+}
+
+impl VoxFile {
+    pub fn save(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()
+    }
+
+    /// Writes every model with a transform node under a single group, so
+    /// MagicaVoxel keeps their placement.
+    pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut children = Vec::new();
+
+        for model in self.models.iter() {
+            write_chunk(
+                &mut children,
+                b"SIZE",
+                &[model.size.x, model.size.y, model.size.z]
+                    .iter()
+                    .flat_map(|v| v.to_le_bytes())
+                    .collect::<Vec<_>>(),
+            );
+
+            let mut content = (model.voxels.len() as u32).to_le_bytes().to_vec();
+            for (position, color) in model.voxels.iter() {
+                content.extend_from_slice(position);
+                content.push(*color);
+            }
+            write_chunk(&mut children, b"XYZI", &content);
+        }
+
+        // Node 0 is the root transform, node 1 the group holding a transform
+        // and shape pair per instance
+        let mut root = Vec::new();
+        write_transform(&mut root, 0, 1, &IDENTITY, cgmath::vec3(0, 0, 0));
+        write_chunk(&mut children, b"nTRN", &root);
+
+        let mut group = 1i32.to_le_bytes().to_vec();
+        write_dict(&mut group, &[]);
+        group.extend((self.instances.len() as i32).to_le_bytes());
+        for i in 0..self.instances.len() as i32 {
+            group.extend((2 + i * 2).to_le_bytes());
+        }
+        write_chunk(&mut children, b"nGRP", &group);
+
+        for (i, instance) in self.instances.iter().enumerate() {
+            let id = 2 + i as i32 * 2;
+
+            let mut transform = Vec::new();
+            write_transform(
+                &mut transform,
+                id,
+                id + 1,
+                &instance.rotation,
+                instance.translation,
+            );
+            write_chunk(&mut children, b"nTRN", &transform);
+
+            let mut shape = (id + 1).to_le_bytes().to_vec();
+            write_dict(&mut shape, &[]);
+            shape.extend(1i32.to_le_bytes());
+            shape.extend((instance.model as i32).to_le_bytes());
+            write_dict(&mut shape, &[]);
+            write_chunk(&mut children, b"nSHP", &shape);
+        }
+
+        // Color index i is stored at entry i - 1
+        let mut rgba = self.palette[1..]
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        rgba.extend([0; 4]);
+        write_chunk(&mut children, b"RGBA", &rgba);
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(b"MAIN")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&(children.len() as u32).to_le_bytes())?;
+        writer.write_all(&children)
+    }
+}
+
+fn write_chunk(data: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    data.extend_from_slice(id);
+    data.extend((content.len() as u32).to_le_bytes());
+    data.extend(0u32.to_le_bytes());
+    data.extend_from_slice(content);
+}
+
+fn write_dict(data: &mut Vec<u8>, entries: &[(&str, String)]) {
+    data.extend((entries.len() as u32).to_le_bytes());
+    for (key, value) in entries {
+        for s in [*key, value.as_str()] {
+            data.extend((s.len() as u32).to_le_bytes());
+            data.extend_from_slice(s.as_bytes());
+        }
+    }
+}
+
+fn write_transform(
+    data: &mut Vec<u8>,
+    id: i32,
+    child: i32,
+    rotation: &Rotation,
+    translation: cgmath::Vector3<i32>,
+) {
+    data.extend(id.to_le_bytes());
+    write_dict(data, &[]);
+    // Child, reserved id, layer and a single frame
+    for value in [child, -1, -1, 1] {
+        data.extend(value.to_le_bytes());
+    }
+
+    let mut frame = Vec::new();
+    if rotation != &IDENTITY {
+        frame.push(("_r", encode_rotation(rotation).to_string()));
+    }
+    if translation != cgmath::vec3(0, 0, 0) {
+        frame.push((
+            "_t",
+            format!("{} {} {}", translation.x, translation.y, translation.z),
+        ));
+    }
+    write_dict(data, &frame);
+}
+
+fn read_chunk<'a>(cursor: &mut Cursor<'a>) -> std::io::Result<(&'a [u8], &'a [u8], &'a [u8])> {
+    let id = cursor.bytes(4)?;
+    let content_size = cursor.u32()? as usize;
+    let children_size = cursor.u32()? as usize;
+    Ok((
+        id,
+        cursor.bytes(content_size)?,
+        cursor.bytes(children_size)?,
+    ))
+}
+
+fn read_string(cursor: &mut Cursor) -> std::io::Result<String> {
+    let length = cursor.u32()? as usize;
+    Ok(String::from_utf8_lossy(cursor.bytes(length)?).into_owned())
+}
+
+fn read_dict(cursor: &mut Cursor) -> std::io::Result<std::collections::HashMap<String, String>> {
+    let count = cursor.u32()?;
+    let mut dict = std::collections::HashMap::new();
+    for _ in 0..count {
+        let key = read_string(cursor)?;
+        let value = read_string(cursor)?;
+        dict.insert(key, value);
+    }
+    Ok(dict)
+}
+
+// Bits 0-1 and 2-3 hold the column of the non zero entry of the first two rows,
+// bits 4-6 the signs of the three rows
+fn decode_rotation(r: u8) -> std::io::Result<Rotation> {
+    let first = (r & 3) as usize;
+    let second = ((r >> 2) & 3) as usize;
+    if first >= 3 || second >= 3 || first == second {
+        return Err(invalid_data(format!("Invalid rotation {}", r)));
+    }
+    let third = 3 - first - second;
+
+    let mut rows = [[0; 3]; 3];
+    for (row, (column, sign_bit)) in [(first, 4), (second, 5), (third, 6)].iter().enumerate() {
+        rows[row][*column] = if r & (1 << sign_bit) != 0 { -1 } else { 1 };
+    }
+    Ok(rows)
+}
+
+fn encode_rotation(rotation: &Rotation) -> u8 {
+    let mut r = 0;
+    for (row, values) in rotation.iter().enumerate() {
+        for (column, value) in values.iter().enumerate() {
+            if *value == 0 {
+                continue;
+            }
+            if row < 2 {
+                r |= (column as u8) << (row * 2);
+            }
+            if *value < 0 {
+                r |= 1 << (4 + row);
+            }
+        }
+    }
+    r
+}
+
+fn collect_instances(
+    nodes: &std::collections::HashMap<i32, Node>,
+    id: i32,
+    rotation: Rotation,
+    translation: cgmath::Vector3<i32>,
+    instances: &mut Vec<VoxInstance>,
+    depth: usize,
+) -> std::io::Result<()> {
+    // Guards against cycles in malformed files
+    if depth > 64 {
+        return Err(invalid_data("Scene graph is too deep".to_string()));
+    }
+
+    match nodes.get(&id) {
+        Some(Node::Transform {
+            child,
+            rotation: r,
+            translation: t,
+        }) => collect_instances(
+            nodes,
+            *child,
+            compose(&rotation, r),
+            rotate(&rotation, *t) + translation,
+            instances,
+            depth + 1,
+        ),
+        Some(Node::Group { children }) => {
+            for child in children {
+                collect_instances(nodes, *child, rotation, translation, instances, depth + 1)?;
+            }
+            Ok(())
+        }
+        Some(Node::Shape { models }) => {
+            instances.extend(models.iter().map(|model| VoxInstance {
+                model: *model,
+                rotation,
+                translation,
+            }));
+            Ok(())
+        }
+        None => Err(invalid_data(format!("Unknown scene node {}", id))),
+    }
+}
+
+// MagicaVoxel's palette when a file has no RGBA chunk: a 6x6x6 color cube
+// without black, followed by red, green, blue and grey ramps
+pub fn default_palette() -> [[u8; 4]; 256] {
+    let mut palette = [[0; 4]; 256];
+    let mut i = 1;
+
+    let steps = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    for r in steps {
+        for g in steps {
+            for b in steps {
+                if i < 216 {
+                    palette[i] = [r, g, b, 0xff];
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    let ramp = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    for channel in 0..4 {
+        for value in ramp {
+            let mut color = [0, 0, 0, 0xff];
+            if channel == 3 {
+                color = [value, value, value, 0xff];
+            } else {
+                color[channel] = value;
+            }
+            palette[i] = color;
+            i += 1;
+        }
+    }
+
+    palette
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], content: &[u8], children: &[u8]) -> Vec<u8> {
+        let mut data = id.to_vec();
+        data.extend((content.len() as u32).to_le_bytes());
+        data.extend((children.len() as u32).to_le_bytes());
+        data.extend(content);
+        data.extend(children);
+        data
+    }
+
+    fn dict(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut data = (entries.len() as u32).to_le_bytes().to_vec();
+        for (key, value) in entries {
+            for s in [key, value] {
+                data.extend((s.len() as u32).to_le_bytes());
+                data.extend(s.as_bytes());
+            }
+        }
+        data
+    }
+
+    fn ints(values: &[i32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn file(children: Vec<u8>) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend(150i32.to_le_bytes());
+        data.extend(chunk(b"MAIN", &[], &children));
+        data
+    }
+
+    fn model(size: [i32; 3], voxels: &[[u8; 4]]) -> Vec<u8> {
+        let mut data = chunk(b"SIZE", &ints(&size), &[]);
+        let mut xyzi = (voxels.len() as u32).to_le_bytes().to_vec();
+        xyzi.extend(voxels.iter().flatten());
+        data.extend(chunk(b"XYZI", &xyzi, &[]));
+        data
+    }
+
+    #[test]
+    fn reads_models_and_palette() {
+        let mut children = model([2, 2, 2], &[[0, 0, 0, 1], [1, 1, 1, 2]]);
+        children.extend(model([1, 1, 1], &[[0, 0, 0, 3]]));
+        let mut rgba = vec![0; 256 * 4];
+        rgba[..12].copy_from_slice(&[255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255]);
+        children.extend(chunk(b"RGBA", &rgba, &[]));
+
+        let vox = VoxFile::read(&mut file(children).as_slice()).unwrap();
+        assert_eq!(vox.models.len(), 2);
+        assert_eq!(vox.instances.len(), 2);
+        assert_eq!(vox.palette[1], [255, 0, 0, 255]);
+        assert_eq!(vox.palette[3], [0, 0, 255, 255]);
+
+        // Centered on the model and converted to y-up
+        let voxels = vox.voxels();
+        assert_eq!(voxels[0], (cgmath::vec3(-1, -1, 1), [255, 0, 0, 255]));
+        assert_eq!(voxels[1], (cgmath::vec3(0, 0, 0), [0, 255, 0, 255]));
+        assert_eq!(voxels[2], (cgmath::vec3(0, 0, 0), [0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn applies_scene_graph_transforms() {
+        let mut children = model([1, 1, 1], &[[0, 0, 0, 1]]);
+        children.extend(model([3, 1, 1], &[[2, 0, 0, 1]]));
+
+        let mut root = ints(&[0]);
+        root.extend(dict(&[]));
+        root.extend(ints(&[1, -1, -1, 1]));
+        root.extend(dict(&[]));
+        children.extend(chunk(b"nTRN", &root, &[]));
+
+        let mut group = ints(&[1]);
+        group.extend(dict(&[]));
+        group.extend(ints(&[2, 2, 4]));
+        children.extend(chunk(b"nGRP", &group, &[]));
+
+        for (id, shape, model, attributes) in [
+            (2, 3, 0, vec![("_t", "10 0 5")]),
+            // Rotated by 90 degrees around z: x -> y
+            (4, 5, 1, vec![("_r", "17")]),
+        ] {
+            let mut transform = ints(&[id]);
+            transform.extend(dict(&[]));
+            transform.extend(ints(&[shape, -1, -1, 1]));
+            transform.extend(dict(&attributes));
+            children.extend(chunk(b"nTRN", &transform, &[]));
+
+            let mut node = ints(&[shape]);
+            node.extend(dict(&[]));
+            node.extend(ints(&[1, model]));
+            node.extend(dict(&[]));
+            children.extend(chunk(b"nSHP", &node, &[]));
+        }
+
+        let vox = VoxFile::read(&mut file(children).as_slice()).unwrap();
+        assert_eq!(vox.instances.len(), 2);
+
+        let positions = vox.voxels().into_iter().map(|(p, _)| p).collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            vec![cgmath::vec3(10, 5, 0), cgmath::vec3(0, 0, -1)]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_rotations() {
+        // Both rows in column 3, then both in column 0
+        for r in ["15", "0"] {
+            let mut children = model([1, 1, 1], &[[0, 0, 0, 1]]);
+            let mut transform = ints(&[0]);
+            transform.extend(dict(&[]));
+            transform.extend(ints(&[1, -1, -1, 1]));
+            transform.extend(dict(&[("_r", r)]));
+            children.extend(chunk(b"nTRN", &transform, &[]));
+
+            let mut node = ints(&[1]);
+            node.extend(dict(&[]));
+            node.extend(ints(&[1, 0]));
+            node.extend(dict(&[]));
+            children.extend(chunk(b"nSHP", &node, &[]));
+
+            assert!(VoxFile::read(&mut file(children).as_slice()).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_voxel_counts_past_the_chunk() {
+        let mut children = chunk(b"SIZE", &ints(&[1, 1, 1]), &[]);
+        let mut voxels = u32::MAX.to_le_bytes().to_vec();
+        voxels.extend_from_slice(&[0, 0, 0, 1]);
+        children.extend(chunk(b"XYZI", &voxels, &[]));
+
+        match VoxFile::read(&mut file(children).as_slice()) {
+            Err(e) => assert!(e.to_string().contains("no room"), "{}", e),
+            Ok(_) => panic!("Read more voxels than the chunk holds"),
+        }
+    }
+
+    #[test]
+    fn write_round_trip() {
+        let mut palette = default_palette();
+        palette[7] = [10, 20, 30, 255];
+        let vox = VoxFile {
+            models: vec![
+                VoxModel {
+                    size: cgmath::vec3(256, 2, 3),
+                    voxels: vec![([255, 0, 0], 7), ([0, 1, 2], 1)],
+                },
+                VoxModel {
+                    size: cgmath::vec3(1, 1, 1),
+                    voxels: vec![([0, 0, 0], 2)],
+                },
+            ],
+            palette,
+            instances: vec![
+                VoxInstance {
+                    model: 0,
+                    rotation: IDENTITY,
+                    translation: cgmath::vec3(128, -1, 1),
+                },
+                VoxInstance {
+                    model: 1,
+                    rotation: [[0, -1, 0], [1, 0, 0], [0, 0, 1]],
+                    translation: cgmath::vec3(-4, 0, 300),
+                },
+            ],
+        };
+
+        let mut data = Vec::new();
+        vox.write(&mut data).unwrap();
+        let loaded = VoxFile::read(&mut data.as_slice()).unwrap();
+
+        assert_eq!(loaded.palette, vox.palette);
+        assert_eq!(loaded.models.len(), 2);
+        assert_eq!(loaded.models[0].voxels, vox.models[0].voxels);
+        assert_eq!(loaded.instances[1].rotation, vox.instances[1].rotation);
+        assert_eq!(loaded.voxels(), vox.voxels());
+    }
+
+    #[test]
+    fn default_palette_is_complete() {
+        let palette = default_palette();
+        assert_eq!(palette[0], [0, 0, 0, 0]);
+        assert_eq!(palette[1], [255, 255, 255, 255]);
+        assert_eq!(palette[215], [0, 0, 0x33, 255]);
+        assert_eq!(palette[255], [0x11, 0x11, 0x11, 255]);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(VoxFile::read(&mut b"SHRG\x01\x00".as_slice()).is_err());
+
+        let children = chunk(b"XYZI", &ints(&[0]), &[]);
+        assert!(VoxFile::read(&mut file(children).as_slice()).is_err());
+    }
+}
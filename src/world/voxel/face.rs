@@ -1,7 +1,7 @@
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
-    position: [f32; 3],
+    pub position: [f32; 3],
 }
 
 impl Vertex {
@@ -38,82 +38,146 @@ pub const INDICES: &[u16] = &[
     0, 2, 3, // 2
 ];
 
-#[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct FaceInstanceModelRaw {
-    model: [[f32; 4]; 4],
-}
+pub const FACE_BACK: u32 = 0;
+pub const FACE_LEFT: u32 = 1;
+pub const FACE_FRONT: u32 = 2;
+pub const FACE_RIGHT: u32 = 3;
+pub const FACE_UP: u32 = 4;
+pub const FACE_DOWN: u32 = 5;
+
+pub const NORMALS: [[f32; 3]; 6] = [
+    [0.0, 0.0, -1.0],
+    [-1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [1.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [0.0, -1.0, 0.0],
+];
+
+// Where the unit quad of every face starts and where its x and y axes point,
+// relative to the voxel's corner. Matches `face_corner` in cube.wgsl.
+pub const ORIGINS: [[f32; 3]; 6] = [
+    [0.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [1.0, 0.0, 1.0],
+    [1.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [0.0, 0.0, 1.0],
+];
+
+pub const AXES: [[[f32; 3]; 2]; 6] = [
+    [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+    [[0.0, 0.0, -1.0], [0.0, 1.0, 0.0]],
+    [[-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+    [[0.0, 0.0, 1.0], [0.0, 1.0, 0.0]],
+    [[1.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+    [[1.0, 0.0, 0.0], [0.0, 0.0, -1.0]],
+];
+
+const POSITION_BITS: u32 = 5;
+const POSITION_MASK: u32 = (1 << POSITION_BITS) - 1;
+const FACE_SHIFT: u32 = 3 * POSITION_BITS;
+const BLOCK_SHIFT: u32 = FACE_SHIFT + 3;
 
+/// A rendered face packed into a u32, the position is local to the chunk:
+/// x, y and z in 5 bits each, then the face in 3 bits and the block above.
+/// Hidden faces don't get one.
 #[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct FaceInstanceRenderRaw {
-    render: u32,
+#[derive(Copy, Clone, Debug, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FaceInstanceRaw {
+    data: u32,
 }
 
-impl FaceInstanceModelRaw {
-    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<FaceInstanceModelRaw>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 6,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-                    shader_location: 7,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
-                    shader_location: 8,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-            ],
+impl FaceInstanceRaw {
+    pub fn new(
+        local: cgmath::Vector3<i32>,
+        face: u32,
+        block: crate::world::block::BlockId,
+    ) -> Self {
+        debug_assert!([local.x, local.y, local.z]
+            .iter()
+            .all(|i| (0..=POSITION_MASK as i32).contains(i)));
+
+        Self {
+            data: local.x as u32
+                | (local.y as u32) << POSITION_BITS
+                | (local.z as u32) << (2 * POSITION_BITS)
+                | face << FACE_SHIFT
+                | (block as u32) << BLOCK_SHIFT,
         }
     }
-}
 
-impl FaceInstanceRenderRaw {
+    pub fn local(&self) -> cgmath::Vector3<i32> {
+        let axis = |i: u32| ((self.data >> (i * POSITION_BITS)) & POSITION_MASK) as i32;
+        cgmath::vec3(axis(0), axis(1), axis(2))
+    }
+
+    pub fn face(&self) -> u32 {
+        (self.data >> FACE_SHIFT) & 0b111
+    }
+
+    pub fn block(&self) -> crate::world::block::BlockId {
+        (self.data >> BLOCK_SHIFT) as crate::world::block::BlockId
+    }
+
+    /// Corners of the face quad in the order of `VERTICES`, for a chunk at `origin`.
+    pub fn corners(&self, origin: cgmath::Vector3<i32>) -> [[f32; 3]; 4] {
+        let position = origin + self.local();
+        let face = self.face() as usize;
+        let [x_axis, y_axis] = AXES[face];
+
+        [0, 1, 2, 3].map(|corner| {
+            let [u, v, _] = VERTICES[corner].position;
+            [0, 1, 2].map(|i| {
+                [position.x, position.y, position.z][i] as f32
+                    + ORIGINS[face][i]
+                    + u * x_axis[i]
+                    + v * y_axis[i]
+            })
+        })
+    }
+
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        use std::mem;
         wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<FaceInstanceRenderRaw>() as wgpu::BufferAddress,
+            array_stride: std::mem::size_of::<FaceInstanceRaw>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Instance,
             attributes: &[wgpu::VertexAttribute {
                 offset: 0,
-                shader_location: 9,
+                shader_location: 5,
                 format: wgpu::VertexFormat::Uint32,
             }],
         }
     }
 }
 
-pub struct FaceInstance {
-    pub position: cgmath::Vector3<f32>,
-    pub rotation: cgmath::Quaternion<f32>,
-    pub render: bool,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl FaceInstance {
-    pub fn to_raw(&self) -> (FaceInstanceModelRaw, FaceInstanceRenderRaw) {
-        (
-            FaceInstanceModelRaw {
-                model: (cgmath::Matrix4::from_translation(self.position)
-                    * cgmath::Matrix4::from(self.rotation))
-                .into(),
-            },
-            FaceInstanceRenderRaw {
-                render: if !self.render { 0 } else { 1 },
-            },
-        )
+    #[test]
+    fn packs_and_unpacks() {
+        let raw = FaceInstanceRaw::new(cgmath::vec3(31, 0, 17), FACE_DOWN, 127);
+        assert_eq!(raw.local(), cgmath::vec3(31, 0, 17));
+        assert_eq!(raw.face(), FACE_DOWN);
+        assert_eq!(raw.block(), 127);
+    }
+
+    #[test]
+    fn faces_cover_their_side_of_the_voxel() {
+        for face in FACE_BACK..=FACE_DOWN {
+            let corners = FaceInstanceRaw::new(cgmath::vec3(1, 2, 3), face, 0)
+                .corners(cgmath::vec3(32, 0, -32));
+            let normal = NORMALS[face as usize];
+            for corner in corners {
+                for i in 0..3 {
+                    let low = [33.0, 2.0, -29.0][i];
+                    assert!((low..=low + 1.0).contains(&corner[i]));
+                    if normal[i] != 0.0 {
+                        // On the side the normal points to
+                        assert_eq!(corner[i], low + normal[i].max(0.0));
+                    }
+                }
+            }
+        }
     }
 }
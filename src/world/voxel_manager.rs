@@ -2,20 +2,33 @@ use wgpu::util::DeviceExt;
 
 pub struct VoxelManger {
     pub pipeline: wgpu::RenderPipeline,
+    pub mesh_pipeline: wgpu::RenderPipeline,
+    pub meshing_mode: super::mesher::MeshingMode,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
-    pub voxels: Vec<super::voxel::Voxel>,
-    pub instances_model_buffer: wgpu::Buffer,
-    pub instances_render_buffer: wgpu::Buffer,
+    pub chunks: std::collections::HashMap<cgmath::Vector3<i32>, super::chunk::Chunk>,
+    pub dirty_chunks: std::collections::HashSet<cgmath::Vector3<i32>>,
+    // Lowest and highest chunk y of every chunk column, for ground lookups
+    columns: std::collections::HashMap<(i32, i32), (i32, i32)>,
+    pub block_registry: super::block::BlockRegistry,
+    pub block_buffer: wgpu::Buffer,
+    pub block_bind_group: wgpu::BindGroup,
+    // Block textures and their sampler
+    pub texture_bind_group: wgpu::BindGroup,
+    pub chunk_bind_group_layout: wgpu::BindGroupLayout,
+    // Chunk bundles draw indirectly with the arguments this writes on the GPU
+    // when set, the slot of a chunk is the index of its bundle
+    pub gpu_culling: Option<crate::common::culling::GpuCulling>,
 }
 
 impl VoxelManger {
     pub fn new(
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         config: &wgpu::SurfaceConfiguration,
         camera_bind_group_layout: &wgpu::BindGroupLayout,
         depth_stencil: Option<wgpu::DepthStencilState>,
-        voxel_number: u32,
+        meshing_mode: super::mesher::MeshingMode,
     ) -> Self {
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shader - Voxel Manager"),
@@ -34,22 +47,136 @@ impl VoxelManger {
             usage: wgpu::BufferUsages::INDEX,
         });
 
+        let block_registry = super::block::BlockRegistry::new();
+
+        let block_buffer = block_registry.create_buffer(device);
+
+        let block_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("Bind Group Layout - Block Registry"),
+            });
+
+        let block_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bind Group - Block Registry"),
+            layout: &block_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: block_buffer.as_entire_binding(),
+            }],
+        });
+
+        let texture = crate::common::texture::Texture::create_array(
+            device,
+            queue,
+            block_registry.textures(),
+            "Texture - Blocks",
+        );
+        // Nearest for crisp texels up close, greedy quads repeat the texture
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Sampler - Blocks"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("Bind Group Layout - Block Textures"),
+            });
+
+        let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bind Group - Block Textures"),
+            layout: &texture_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let chunk_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("Bind Group Layout - Chunk Origin"),
+            });
+
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout - Voxel Manager"),
-            bind_group_layouts: &[camera_bind_group_layout],
+            bind_group_layouts: &[
+                camera_bind_group_layout,
+                &block_bind_group_layout,
+                &texture_bind_group_layout,
+            ],
             push_constant_ranges: &[],
         });
 
+        let instanced_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout - Voxel Manager Instanced"),
+                bind_group_layouts: &[
+                    camera_bind_group_layout,
+                    &block_bind_group_layout,
+                    &texture_bind_group_layout,
+                    &chunk_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
         let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render Pipeline - Voxel Manager"),
-            layout: Some(&pipeline_layout),
+            layout: Some(&instanced_pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &shader,
                 entry_point: "vs_main",
                 buffers: &[
                     super::voxel::face::Vertex::desc(),
-                    super::voxel::face::FaceInstanceModelRaw::desc(),
-                    super::voxel::face::FaceInstanceRenderRaw::desc(),
+                    super::voxel::face::FaceInstanceRaw::desc(),
                 ],
             },
             primitive: wgpu::PrimitiveState {
@@ -61,7 +188,7 @@ impl VoxelManger {
                 unclipped_depth: false,
                 conservative: false,
             },
-            depth_stencil,
+            depth_stencil: depth_stencil.clone(),
             multisample: wgpu::MultisampleState {
                 count: 1,
                 mask: !0,
@@ -72,167 +199,610 @@ impl VoxelManger {
                 entry_point: "fs_main",
                 targets: &[Some(wgpu::ColorTargetState {
                     format: config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             }),
             multiview: None,
         });
 
-        let voxels = Self::gen_voxels((voxel_number as f32).sqrt().ceil() as u32);
-
-        let instances_model_data = voxels
-            .iter()
-            .map(|v| v.get_data().0)
-            .into_iter()
-            .flatten()
-            .collect::<Vec<_>>();
-
-        let instances_render_data = voxels
-            .iter()
-            .map(|v| v.get_data().1)
-            .into_iter()
-            .flatten()
-            .collect::<Vec<_>>();
-
-        let instances_model_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Model Buffer - Voxel Manager"),
-            contents: bytemuck::cast_slice(&instances_model_data),
-            usage: wgpu::BufferUsages::VERTEX,
+        let mesh_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader - Voxel Manager Mesh"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../assets/shaders/mesh.wgsl").into()),
         });
 
-        let instances_render_buffer =
-            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Render Buffer - Voxel Manager"),
-                contents: bytemuck::cast_slice(&instances_render_data),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
+        let mesh_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render Pipeline - Voxel Manager Mesh"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &mesh_shader,
+                entry_point: "vs_main",
+                buffers: &[super::mesher::MeshVertex::desc()],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &mesh_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
 
         Self {
             pipeline,
+            mesh_pipeline,
+            meshing_mode,
             vertex_buffer,
             index_buffer,
-            voxels,
-            instances_model_buffer,
-            instances_render_buffer,
+            chunks: std::collections::HashMap::new(),
+            dirty_chunks: std::collections::HashSet::new(),
+            columns: std::collections::HashMap::new(),
+            block_registry,
+            block_buffer,
+            block_bind_group,
+            texture_bind_group,
+            chunk_bind_group_layout,
+            gpu_culling: None,
         }
     }
 
-    fn gen_voxels(voxel_number: u32) -> Vec<super::voxel::Voxel> {
-        (0..voxel_number)
-            .flat_map(|z| {
-                (0..voxel_number)
-                    .map(move |x| super::voxel::Voxel::new(&cgmath::vec3(x as f32, 0.0, z as f32)))
+    pub fn gen_terrain(mut self, generator: &super::terrain::TerrainGenerator) -> Self {
+        generator.generate(&mut self);
+        self
+    }
+
+    pub fn get_voxel(&self, position: cgmath::Vector3<i32>) -> super::block::BlockId {
+        self.chunks
+            .get(&super::chunk::chunk_position(position))
+            .map_or(super::block::AIR, |chunk| {
+                chunk.get_voxel(super::chunk::local_position(position))
             })
-            .collect::<Vec<_>>()
     }
 
-    pub fn update_buffers(mut self, device: &wgpu::Device) -> Self {
-        self.instances_render_buffer.destroy();
-        self.instances_model_buffer.destroy();
+    pub fn set_voxel(&mut self, position: cgmath::Vector3<i32>, block: super::block::BlockId) {
+        if block == super::block::AIR {
+            return self.remove_voxel(position);
+        }
 
-        let instances_model_data = self
-            .voxels
-            .iter()
-            .map(|v| v.get_data().0)
-            .into_iter()
-            .flatten()
-            .collect::<Vec<_>>();
+        let chunk_position = super::chunk::chunk_position(position);
+        match self.chunks.get_mut(&chunk_position) {
+            Some(chunk) => chunk.set_voxel(super::chunk::local_position(position), block),
+            None => {
+                let mut chunk = super::chunk::Chunk::new(chunk_position);
+                chunk.set_voxel(super::chunk::local_position(position), block);
+                self.insert_chunk(chunk);
+            }
+        }
+        self.mark_dirty(position);
+    }
 
-        self.instances_model_buffer =
-            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Model Buffer - Voxel Manager"),
-                contents: bytemuck::cast_slice(&instances_model_data),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
+    pub fn remove_voxel(&mut self, position: cgmath::Vector3<i32>) {
+        // Nothing to remove where no chunk was ever created
+        if let Some(chunk) = self.chunks.get_mut(&super::chunk::chunk_position(position)) {
+            chunk.set_voxel(super::chunk::local_position(position), super::block::AIR);
+            self.mark_dirty(position);
+        }
+    }
 
-        let instances_render_data = self
-            .voxels
-            .iter()
-            .map(|v| v.get_data().1)
-            .into_iter()
-            .flatten()
-            .collect::<Vec<_>>();
+    fn mark_dirty(&mut self, position: cgmath::Vector3<i32>) {
+        let chunk_position = super::chunk::chunk_position(position);
+        let local_position = super::chunk::local_position(position);
+        self.dirty_chunks.insert(chunk_position);
+
+        // Voxels on a chunk border change the culled faces of the neighbour chunk
+        for axis in 0..3 {
+            let mut offset = cgmath::vec3(0, 0, 0);
+            if local_position[axis] == 0 {
+                offset[axis] = -1;
+            } else if local_position[axis] == super::chunk::CHUNK_SIZE - 1 {
+                offset[axis] = 1;
+            } else {
+                continue;
+            }
+
+            if self.chunks.contains_key(&(chunk_position + offset)) {
+                self.dirty_chunks.insert(chunk_position + offset);
+            }
+        }
+    }
+
+    /// Places every model of the scene with its center at `origin`.
+    pub fn stamp_vox(
+        &mut self,
+        vox: &super::vox::VoxFile,
+        origin: cgmath::Vector3<i32>,
+        mapping: super::vox::ColorMapping,
+    ) {
+        let mut blocks = std::collections::HashMap::new();
 
-        self.instances_render_buffer =
-            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Render Buffer - Voxel Manager"),
-                contents: bytemuck::cast_slice(&instances_render_data),
-                usage: wgpu::BufferUsages::VERTEX,
+        for (position, color) in vox.voxels() {
+            let block = *blocks.entry(color).or_insert_with(|| match mapping {
+                super::vox::ColorMapping::Exact => self.block_registry.register_color(color),
+                super::vox::ColorMapping::Nearest => self.block_registry.nearest(color),
             });
+            self.set_voxel(origin + position, block);
+        }
+    }
+
+    // Corners of the chunks holding blocks
+    pub fn bounds(&self) -> Option<(cgmath::Vector3<i32>, cgmath::Vector3<i32>)> {
+        self.chunks
+            .values()
+            .filter(|chunk| !chunk.is_empty())
+            .map(|chunk| {
+                let origin = chunk.origin();
+                (
+                    origin,
+                    origin + cgmath::vec3(1, 1, 1) * (super::chunk::CHUNK_SIZE - 1),
+                )
+            })
+            .reduce(|(min, max), (lo, hi)| {
+                (
+                    cgmath::vec3(min.x.min(lo.x), min.y.min(lo.y), min.z.min(lo.z)),
+                    cgmath::vec3(max.x.max(hi.x), max.y.max(hi.y), max.z.max(hi.z)),
+                )
+            })
+    }
+
+    /// Copies the blocks inside `min..=max` into a .vox scene, split into
+    /// models MagicaVoxel can hold. Positions are relative to `min`.
+    pub fn export_vox(
+        &self,
+        min: cgmath::Vector3<i32>,
+        max: cgmath::Vector3<i32>,
+    ) -> super::vox::VoxFile {
+        use super::vox::MAX_MODEL_SIZE;
+
+        let mut vox = super::vox::VoxFile {
+            models: Vec::new(),
+            palette: [[0; 4]; 256],
+            instances: Vec::new(),
+        };
+        let mut colors = std::collections::HashMap::new();
+
+        // The box in MagicaVoxel's z-up space, world (x, y, z) is (x, -z, y)
+        let size = cgmath::vec3(max.x - min.x + 1, max.z - min.z + 1, max.y - min.y + 1);
+
+        for tile_z in (0..size.z).step_by(MAX_MODEL_SIZE as usize) {
+            for tile_y in (0..size.y).step_by(MAX_MODEL_SIZE as usize) {
+                for tile_x in (0..size.x).step_by(MAX_MODEL_SIZE as usize) {
+                    let tile = cgmath::vec3(tile_x, tile_y, tile_z);
+                    let tile_size = cgmath::vec3(
+                        (size.x - tile.x).min(MAX_MODEL_SIZE),
+                        (size.y - tile.y).min(MAX_MODEL_SIZE),
+                        (size.z - tile.z).min(MAX_MODEL_SIZE),
+                    );
+
+                    let mut voxels = Vec::new();
+                    for z in 0..tile_size.z {
+                        for y in 0..tile_size.y {
+                            for x in 0..tile_size.x {
+                                let position = tile + cgmath::vec3(x, y, z);
+                                let block = self.get_voxel(
+                                    min + cgmath::vec3(
+                                        position.x,
+                                        position.z,
+                                        size.y - 1 - position.y,
+                                    ),
+                                );
+                                if block == super::block::AIR {
+                                    continue;
+                                }
+
+                                let next = colors.len() + 1;
+                                let color = *colors.entry(block).or_insert(next);
+                                vox.palette[color] = self
+                                    .block_registry
+                                    .get(block)
+                                    .color()
+                                    .map(|c| (c * 255.0).round() as u8);
+                                voxels.push(([x as u8, y as u8, z as u8], color as u8));
+                            }
+                        }
+                    }
+
+                    if voxels.is_empty() {
+                        continue;
+                    }
+
+                    // Models are centered on their transform
+                    let mut translation = tile + tile_size / 2;
+                    translation.y -= size.y - 1;
+                    vox.instances.push(super::vox::VoxInstance {
+                        model: vox.models.len(),
+                        rotation: super::vox::IDENTITY,
+                        translation,
+                    });
+                    vox.models.push(super::vox::VoxModel {
+                        size: tile_size,
+                        voxels,
+                    });
+                }
+            }
+        }
+
+        vox
+    }
+
+    /// Collects the faces left after culling, as meshed by the current mode.
+    pub fn export_mesh(&self) -> super::export::MeshExport {
+        let mut export = super::export::MeshExport::new();
+
+        let mut positions = self.chunks.keys().copied().collect::<Vec<_>>();
+        // Stable output, so exports of the same world can be diffed
+        positions.sort_by_key(|p| (p.y, p.z, p.x));
+
+        for chunk in positions.iter().map(|p| &self.chunks[p]) {
+            match self.meshing_mode {
+                super::mesher::MeshingMode::Instanced => {
+                    export.push_instances(
+                        &self.block_registry,
+                        chunk.origin(),
+                        chunk.instance_data(),
+                    );
+                }
+                super::mesher::MeshingMode::Greedy => {
+                    export.push_mesh(&self.block_registry, &chunk.mesh)
+                }
+            }
+        }
+
+        export
+    }
+
+    pub fn height_at(&self, x: i32, z: i32) -> Option<i32> {
+        let column = super::chunk::chunk_position(cgmath::vec3(x, 0, z));
+        let local = super::chunk::local_position(cgmath::vec3(x, 0, z));
+        let (bottom, top) = *self.columns.get(&(column.x, column.z))?;
+
+        (bottom..=top).rev().find_map(|y| {
+            let chunk = self
+                .chunks
+                .get(&cgmath::vec3(column.x, y, column.z))
+                .filter(|chunk| !chunk.is_empty())?;
+            (0..super::chunk::CHUNK_SIZE)
+                .rev()
+                .find(|y| chunk.get_voxel(cgmath::vec3(local.x, *y, local.z)) != super::block::AIR)
+                .map(|local_y| y * super::chunk::CHUNK_SIZE + local_y)
+        })
+    }
+
+    fn insert_chunk(&mut self, chunk: super::chunk::Chunk) {
+        let position = chunk.position;
+        self.columns
+            .entry((position.x, position.z))
+            .and_modify(|(bottom, top)| {
+                *bottom = position.y.min(*bottom);
+                *top = position.y.max(*top);
+            })
+            .or_insert((position.y, position.y));
+        self.chunks.insert(position, chunk);
+    }
+
+    pub fn save(&self, path: &std::path::Path) -> std::io::Result<()> {
+        use std::io::Write;
+
+        std::fs::create_dir_all(path)?;
+
+        let mut regions = std::collections::HashMap::<_, Vec<_>>::new();
+        for chunk in self.chunks.values().filter(|chunk| !chunk.is_empty()) {
+            regions
+                .entry(super::region::region_position(chunk.position))
+                .or_default()
+                .push(chunk);
+        }
+
+        let mut written = std::collections::HashSet::new();
+        for (position, chunks) in regions {
+            let file_path = path.join(super::region::file_name(position));
+            let mut file = std::io::BufWriter::new(std::fs::File::create(&file_path)?);
+            super::region::write_region(&mut file, &chunks, &self.block_registry)?;
+            file.flush()?;
+            written.insert(file_path);
+        }
+
+        // Regions that became empty since the last save would resurrect old chunks
+        for entry in std::fs::read_dir(path)? {
+            let file_path = entry?.path();
+            if file_path.extension() == Some(super::region::REGION_EXTENSION.as_ref())
+                && !written.contains(&file_path)
+            {
+                std::fs::remove_file(file_path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn load(&mut self, path: &std::path::Path) -> std::io::Result<()> {
+        let mut loaded = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let file_path = entry?.path();
+            if file_path.extension() == Some(super::region::REGION_EXTENSION.as_ref()) {
+                let mut file = std::io::BufReader::new(std::fs::File::open(file_path)?);
+                loaded.extend(super::region::read_region(
+                    &mut file,
+                    &mut self.block_registry,
+                )?);
+            }
+        }
+
+        // Existing chunks keep their bundle slot, so empty them instead of removing them
+        for chunk in self.chunks.values_mut() {
+            chunk.set_blocks(vec![super::block::AIR; super::chunk::CHUNK_VOLUME]);
+        }
+        for chunk in loaded {
+            match self.chunks.get_mut(&chunk.position) {
+                Some(existing) => existing.set_blocks(chunk.blocks().to_vec()),
+                None => self.insert_chunk(chunk),
+            }
+        }
+        self.dirty_chunks.extend(self.chunks.keys().copied());
+
+        Ok(())
+    }
+
+    pub fn update_buffers(mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        self.block_registry.update_buffer(queue, &self.block_buffer);
+
+        for chunk in self.chunks.values_mut() {
+            match self.meshing_mode {
+                super::mesher::MeshingMode::Instanced => {
+                    chunk.update_buffers(device, queue, &self.chunk_bind_group_layout)
+                }
+                super::mesher::MeshingMode::Greedy => chunk.mesh.update_buffers(device, queue),
+            }
+        }
 
         self
     }
 
     pub fn update_map(mut self) -> Self {
-        for (index, n) in self
-            .get_neighbours()
-            .iter()
-            .enumerate()
-            .take(self.voxels.len())
-        {
-            self.voxels[index].set_faces(
-                Some(n[0]),
-                Some(n[1]),
-                Some(n[2]),
-                Some(n[3]),
-                Some(n[4]),
-                Some(n[5]),
-            );
+        let positions = self.chunks.keys().copied().collect::<Vec<_>>();
 
-            self.voxels[index].update_instance_data();
+        for position in positions {
+            self.update_chunk(position);
         }
+        self.dirty_chunks.clear();
+
+        let (vertices, indices) =
+            self.chunks
+                .values()
+                .fold((0, 0), |(v, i), chunk| match self.meshing_mode {
+                    super::mesher::MeshingMode::Instanced => (
+                        v + chunk.instance_count() as usize * super::voxel::face::VERTICES.len(),
+                        i + chunk.instance_count() as usize * super::voxel::face::INDICES.len(),
+                    ),
+                    super::mesher::MeshingMode::Greedy => {
+                        (v + chunk.mesh.vertices.len(), i + chunk.mesh.indices.len())
+                    }
+                });
+        log::info!(
+            "Voxel Manager: {:?} meshing, {} chunks, {} vertices, {} indices",
+            self.meshing_mode,
+            self.chunks.len(),
+            vertices,
+            indices
+        );
 
         self
     }
 
-    fn get_neighbours(&self) -> Vec<Vec<bool>> {
-        let mut ns = Vec::new();
-        for v in self.voxels.iter() {
-            ns.push(self.get_neighbour(v));
+    fn update_chunk(&mut self, position: cgmath::Vector3<i32>) {
+        if self.meshing_mode == super::mesher::MeshingMode::Greedy {
+            let mesh = match self.chunks.get(&position) {
+                Some(chunk) => super::mesher::ChunkMesh::greedy(chunk, |p, block, offset| {
+                    self.hides(p, block, offset)
+                }),
+                None => return,
+            };
+
+            if let Some(chunk) = self.chunks.get_mut(&position) {
+                chunk.mesh = mesh;
+            }
+            return;
         }
-        ns
-    }
-
-    fn get_neighbour(&self, v: &super::voxel::Voxel) -> Vec<bool> {
-        let front = self
-            .voxels
-            .iter()
-            .any(|voxel| voxel.position.z == v.position.z + 1.0);
-        let back = self
-            .voxels
-            .iter()
-            .any(|voxel| voxel.position.z == v.position.z - 1.0);
-        let left = self
-            .voxels
-            .iter()
-            .any(|voxel| voxel.position.x == v.position.x - 1.0);
-        let right = self
-            .voxels
-            .iter()
-            .any(|voxel| voxel.position.x == v.position.x + 1.0);
-        let up = self
-            .voxels
-            .iter()
-            .any(|voxel| voxel.position.y == v.position.y + 1.0);
-        let down = self
-            .voxels
-            .iter()
-            .any(|voxel| voxel.position.y == v.position.y - 1.0);
+
+        let voxels = match self.chunks.get(&position) {
+            Some(chunk) => chunk
+                .voxels()
+                .map(|(p, block)| {
+                    let mut voxel =
+                        super::voxel::Voxel::new(super::chunk::local_position(p), block);
+                    let n = self.get_neighbour(p, block);
+
+                    voxel.set_faces(
+                        Some(n[0]),
+                        Some(n[1]),
+                        Some(n[2]),
+                        Some(n[3]),
+                        Some(n[4]),
+                        Some(n[5]),
+                    );
+                    voxel.update_instance_data();
+
+                    voxel
+                })
+                .collect::<Vec<_>>(),
+            None => return,
+        };
+
+        if let Some(chunk) = self.chunks.get_mut(&position) {
+            chunk.set_instance_data(&voxels);
+        }
+    }
+
+    fn get_neighbour(
+        &self,
+        position: cgmath::Vector3<i32>,
+        block: super::block::BlockId,
+    ) -> Vec<bool> {
+        let hides = |offset| self.hides(position, block, offset);
+
+        let front = hides(cgmath::vec3(0, 0, 1));
+        let back = hides(cgmath::vec3(0, 0, -1));
+        let left = hides(cgmath::vec3(-1, 0, 0));
+        let right = hides(cgmath::vec3(1, 0, 0));
+        let up = hides(cgmath::vec3(0, 1, 0));
+        let down = hides(cgmath::vec3(0, -1, 0));
         vec![front, back, left, right, up, down]
     }
 
-    pub fn finish_bundle(
+    fn hides(
+        &self,
+        position: cgmath::Vector3<i32>,
+        block: super::block::BlockId,
+        offset: cgmath::Vector3<i32>,
+    ) -> bool {
+        let neighbour = self.get_voxel(position + offset);
+        // Transparent blocks only hide faces shared with the same block
+        self.block_registry.is_opaque(neighbour)
+            || (neighbour == block && neighbour != super::block::AIR)
+    }
+
+    pub fn update_dirty(
+        &mut self,
+        bundle_manager: &mut crate::common::bundles::BundleManager,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        camera_bind_group: &wgpu::BindGroup,
+        depth_stencil: Option<wgpu::RenderBundleDepthStencil>,
+    ) {
+        // Imports and loads can register new blocks
+        self.block_registry.update_buffer(queue, &self.block_buffer);
+
+        let positions = self.dirty_chunks.drain().collect::<Vec<_>>();
+
+        // New chunks can outgrow the indirect buffer every bundle draws from
+        let regrown = match &mut self.gpu_culling {
+            Some(gpu_culling) => {
+                gpu_culling.reserve(device, bundle_manager.len() + positions.len())
+            }
+            None => false,
+        };
+
+        for position in &positions {
+            self.update_chunk(*position);
+
+            if let Some(chunk) = self.chunks.get_mut(position) {
+                match self.meshing_mode {
+                    super::mesher::MeshingMode::Instanced => {
+                        chunk.update_buffers(device, queue, &self.chunk_bind_group_layout)
+                    }
+                    super::mesher::MeshingMode::Greedy => chunk.mesh.update_buffers(device, queue),
+                }
+            }
+        }
+
+        let positions = match regrown {
+            true => self.chunks.keys().copied().collect::<Vec<_>>(),
+            false => positions,
+        };
+        for position in positions {
+            self.finish_chunk_bundle(
+                position,
+                bundle_manager,
+                device,
+                queue,
+                config,
+                camera_bind_group,
+                depth_stencil,
+            );
+        }
+    }
+
+    /// Bundles of the chunks in the frustum, skipping empty chunks. With GPU
+    /// culling every chunk is submitted and the culling pass decides, so the
+    /// stats are the ones it read back from a previous frame.
+    pub fn visible_bundles(
         &self,
+        frustum: &crate::common::frustum::Frustum,
+    ) -> (Vec<usize>, crate::common::frustum::CullStats) {
+        let mut bundles = Vec::new();
+        let mut stats = crate::common::frustum::CullStats::default();
+
+        for chunk in self.chunks.values().filter(|chunk| !chunk.is_empty()) {
+            let (min, max) = chunk.aabb();
+            match chunk.bundle {
+                Some(bundle) if self.gpu_culling.is_some() || frustum.intersects_aabb(min, max) => {
+                    bundles.push(bundle);
+                    stats.drawn += 1;
+                }
+                _ => stats.culled += 1,
+            }
+        }
+
+        if let Some(gpu_culling) = &self.gpu_culling {
+            stats = gpu_culling.stats();
+        }
+        (bundles, stats)
+    }
+
+    pub fn finish_bundle(
+        &mut self,
+        bundle_manager: &mut crate::common::bundles::BundleManager,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        camera_bind_group: &wgpu::BindGroup,
+        depth_stencil: Option<wgpu::RenderBundleDepthStencil>,
+    ) {
+        let positions = self.chunks.keys().copied().collect::<Vec<_>>();
+
+        if let Some(gpu_culling) = &mut self.gpu_culling {
+            gpu_culling.reserve(device, bundle_manager.len() + positions.len());
+        }
+
+        for position in positions {
+            self.finish_chunk_bundle(
+                position,
+                bundle_manager,
+                device,
+                queue,
+                config,
+                camera_bind_group,
+                depth_stencil,
+            );
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn finish_chunk_bundle(
+        &mut self,
+        position: cgmath::Vector3<i32>,
         bundle_manager: &mut crate::common::bundles::BundleManager,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         config: &wgpu::SurfaceConfiguration,
         camera_bind_group: &wgpu::BindGroup,
         depth_stencil: Option<wgpu::RenderBundleDepthStencil>,
     ) {
+        let chunk = match self.chunks.get(&position) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+        // New bundles are pushed at the end
+        let slot = chunk.bundle.unwrap_or(bundle_manager.len());
+
         let mut render_bundle_encoder =
             device.create_render_bundle_encoder(&wgpu::RenderBundleEncoderDescriptor {
                 label: Some("Render Bundle Encoder - Voxel Manager"),
@@ -242,27 +812,301 @@ impl VoxelManger {
                 multiview: None,
             });
 
-        render_bundle_encoder.set_pipeline(&self.pipeline);
+        match self.meshing_mode {
+            super::mesher::MeshingMode::Instanced => {
+                if let (Some(instances_buffer), Some(origin_bind_group), false) = (
+                    chunk.instances_buffer.as_ref(),
+                    chunk.origin_bind_group.as_ref(),
+                    chunk.is_empty(),
+                ) {
+                    render_bundle_encoder.set_pipeline(&self.pipeline);
 
-        render_bundle_encoder.set_bind_group(0, camera_bind_group, &[]);
+                    render_bundle_encoder.set_bind_group(0, camera_bind_group, &[]);
+                    render_bundle_encoder.set_bind_group(1, &self.block_bind_group, &[]);
+                    render_bundle_encoder.set_bind_group(2, &self.texture_bind_group, &[]);
+                    render_bundle_encoder.set_bind_group(3, origin_bind_group, &[]);
 
-        render_bundle_encoder.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        render_bundle_encoder.set_vertex_buffer(1, self.instances_model_buffer.slice(..));
-        render_bundle_encoder.set_vertex_buffer(2, self.instances_render_buffer.slice(..));
+                    render_bundle_encoder.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                    render_bundle_encoder.set_vertex_buffer(1, instances_buffer.slice(..));
 
-        render_bundle_encoder
-            .set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                    render_bundle_encoder
+                        .set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
 
-        render_bundle_encoder.draw_indexed(
-            0..(super::voxel::face::INDICES.len() as u32),
-            0,
-            0..(self.voxels.len() as u32 * 6),
-        );
+                    Self::draw(
+                        &mut render_bundle_encoder,
+                        self.gpu_culling.as_mut(),
+                        queue,
+                        slot,
+                        chunk,
+                        super::voxel::face::INDICES.len() as u32,
+                        chunk.instance_count(),
+                    );
+                }
+            }
+            super::mesher::MeshingMode::Greedy => {
+                if let (Some(vertex_buffer), Some(index_buffer), false) = (
+                    chunk.mesh.vertex_buffer.as_ref(),
+                    chunk.mesh.index_buffer.as_ref(),
+                    chunk.is_empty(),
+                ) {
+                    render_bundle_encoder.set_pipeline(&self.mesh_pipeline);
+
+                    render_bundle_encoder.set_bind_group(0, camera_bind_group, &[]);
+                    render_bundle_encoder.set_bind_group(1, &self.block_bind_group, &[]);
+                    render_bundle_encoder.set_bind_group(2, &self.texture_bind_group, &[]);
+
+                    render_bundle_encoder.set_vertex_buffer(0, vertex_buffer.slice(..));
+
+                    render_bundle_encoder
+                        .set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+
+                    Self::draw(
+                        &mut render_bundle_encoder,
+                        self.gpu_culling.as_mut(),
+                        queue,
+                        slot,
+                        chunk,
+                        chunk.mesh.indices.len() as u32,
+                        1,
+                    );
+                }
+            }
+        }
 
         let render_bundle = render_bundle_encoder.finish(&wgpu::RenderBundleDescriptor {
-            label: Some("Render Bundle - Voxel Manager"),
+            label: Some("Render Bundle - Chunk"),
+        });
+
+        if let Some(chunk) = self.chunks.get_mut(&position) {
+            match chunk.bundle {
+                Some(index) => bundle_manager.set_bundle(index, render_bundle),
+                None => chunk.bundle = Some(bundle_manager.push_bundle(render_bundle)),
+            }
+        }
+    }
+
+    // Draws the whole chunk, or through its culling slot
+    fn draw<'a>(
+        render_bundle_encoder: &mut wgpu::RenderBundleEncoder<'a>,
+        gpu_culling: Option<&'a mut crate::common::culling::GpuCulling>,
+        queue: &wgpu::Queue,
+        slot: usize,
+        chunk: &super::chunk::Chunk,
+        index_count: u32,
+        instance_count: u32,
+    ) {
+        match gpu_culling {
+            Some(gpu_culling) => {
+                let (min, max) = chunk.aabb();
+                gpu_culling.set_draw(queue, slot, min, max, index_count, instance_count);
+                render_bundle_encoder.draw_indexed_indirect(
+                    &gpu_culling.indirect_buffer,
+                    slot as wgpu::BufferAddress * crate::common::culling::INDIRECT_SIZE,
+                );
+            }
+            None => render_bundle_encoder.draw_indexed(0..index_count, 0, 0..instance_count),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::headless::Headless;
+
+    // Keeps the device and the render lock until the manager is dropped
+    struct TestWorld {
+        voxel_manager: VoxelManger,
+        headless: Headless,
+        _lock: std::sync::MutexGuard<'static, ()>,
+    }
+
+    fn test_world() -> TestWorld {
+        let _lock = crate::common::headless::RENDER_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let headless = pollster::block_on(Headless::new(winit::dpi::PhysicalSize::new(32, 32)));
+        let voxel_manager = VoxelManger::new(
+            &headless.wgpu_manager.device,
+            &headless.wgpu_manager.queue,
+            &headless.wgpu_manager.config,
+            &headless.camera_manager.camera_bind_group_layout,
+            crate::common::state::State::depth_stencil(),
+            super::super::mesher::MeshingMode::Instanced,
+        );
+
+        TestWorld {
+            voxel_manager,
+            headless,
+            _lock,
+        }
+    }
+
+    fn stone(voxel_manager: &VoxelManger) -> super::super::block::BlockId {
+        voxel_manager.block_registry.get_id("stone").unwrap()
+    }
+
+    #[test]
+    fn finds_the_ground_across_stacked_chunks() {
+        let mut world = test_world();
+        let voxel_manager = &mut world.voxel_manager;
+        let stone = stone(voxel_manager);
+
+        voxel_manager.set_voxel(cgmath::vec3(5, -40, 7), stone);
+        voxel_manager.set_voxel(cgmath::vec3(5, 70, 7), stone);
+        // Another column of the same chunks
+        voxel_manager.set_voxel(cgmath::vec3(6, 3, 7), stone);
+
+        assert_eq!(voxel_manager.height_at(5, 7), Some(70));
+        assert_eq!(voxel_manager.height_at(6, 7), Some(3));
+        assert_eq!(voxel_manager.height_at(8, 7), None);
+        assert_eq!(voxel_manager.height_at(-100, 7), None);
+
+        voxel_manager.set_voxel(cgmath::vec3(5, 70, 7), super::super::block::AIR);
+        assert_eq!(voxel_manager.height_at(5, 7), Some(-40));
+    }
+
+    #[test]
+    fn hides_faces_shared_across_chunks() {
+        use super::super::voxel::face::{FACE_LEFT, FACE_RIGHT};
+
+        let mut world = test_world();
+        let stone = stone(&world.voxel_manager);
+        world.voxel_manager.set_voxel(cgmath::vec3(31, 0, 0), stone);
+        world.voxel_manager.set_voxel(cgmath::vec3(32, 0, 0), stone);
+        world.voxel_manager = world.voxel_manager.update_map();
+
+        let faces = |chunk| {
+            world.voxel_manager.chunks[&chunk]
+                .instance_data()
+                .iter()
+                .map(|instance| instance.face())
+                .collect::<Vec<_>>()
+        };
+        let (west, east) = (faces(cgmath::vec3(0, 0, 0)), faces(cgmath::vec3(1, 0, 0)));
+        assert_eq!(west.len(), 5);
+        assert_eq!(east.len(), 5);
+        assert!(!west.contains(&FACE_RIGHT) && west.contains(&FACE_LEFT));
+        assert!(!east.contains(&FACE_LEFT) && east.contains(&FACE_RIGHT));
+    }
+
+    #[test]
+    fn edits_on_a_border_dirty_the_neighbour() {
+        let mut world = test_world();
+        let voxel_manager = &mut world.voxel_manager;
+        let stone = stone(voxel_manager);
+        voxel_manager.set_voxel(cgmath::vec3(10, 0, 0), stone);
+        voxel_manager.set_voxel(cgmath::vec3(40, 0, 0), stone);
+        voxel_manager.dirty_chunks.clear();
+
+        voxel_manager.set_voxel(cgmath::vec3(15, 0, 0), stone);
+        assert_eq!(
+            voxel_manager.dirty_chunks,
+            [cgmath::vec3(0, 0, 0)].into_iter().collect()
+        );
+
+        voxel_manager.set_voxel(cgmath::vec3(31, 0, 0), stone);
+        assert_eq!(
+            voxel_manager.dirty_chunks,
+            [cgmath::vec3(0, 0, 0), cgmath::vec3(1, 0, 0)]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn remeshes_only_dirty_chunks() {
+        let mut world = test_world();
+        let stone = stone(&world.voxel_manager);
+        for x in [31, 32, 100] {
+            world.voxel_manager.set_voxel(cgmath::vec3(x, 0, 0), stone);
+        }
+        world.voxel_manager = world.voxel_manager.update_map();
+
+        // Edited behind the manager's back, so only a re-mesh would show it
+        let far = cgmath::vec3(3, 0, 0);
+        world
+            .voxel_manager
+            .chunks
+            .get_mut(&far)
+            .unwrap()
+            .set_voxel(cgmath::vec3(10, 0, 0), stone);
+        // Removing a border voxel uncovers the face of its neighbour
+        world.voxel_manager.remove_voxel(cgmath::vec3(32, 0, 0));
+
+        let headless = &mut world.headless;
+        world.voxel_manager.update_dirty(
+            &mut headless.bundle_manager,
+            &headless.wgpu_manager.device,
+            &headless.wgpu_manager.queue,
+            &headless.wgpu_manager.config,
+            &headless.camera_manager.camera_bind_group,
+            crate::common::state::State::bundle_depth_stencil(),
+        );
+
+        let faces = |chunk| world.voxel_manager.chunks[&chunk].instance_data().len();
+        assert!(world.voxel_manager.dirty_chunks.is_empty());
+        assert_eq!(faces(cgmath::vec3(0, 0, 0)), 6);
+        assert_eq!(faces(cgmath::vec3(1, 0, 0)), 0);
+        assert_eq!(faces(far), 6);
+    }
+
+    // Blocks relative to the lowest corner of the voxels holding them
+    fn normalized_voxels(
+        voxel_manager: &VoxelManger,
+    ) -> std::collections::HashSet<(cgmath::Vector3<i32>, super::super::block::BlockId)> {
+        let voxels = voxel_manager
+            .chunks
+            .values()
+            .flat_map(|chunk| chunk.voxels())
+            .collect::<Vec<_>>();
+        let min = voxels.iter().fold(voxels[0].0, |min, (p, _)| {
+            cgmath::vec3(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z))
         });
+        voxels
+            .into_iter()
+            .map(|(p, block)| (p - min, block))
+            .collect()
+    }
+
+    #[test]
+    fn exports_large_boxes_as_several_models() {
+        use super::super::vox::{ColorMapping, VoxFile, MAX_MODEL_SIZE};
 
-        bundle_manager.push_bundle(render_bundle);
+        let mut world = test_world();
+        let voxel_manager = &mut world.voxel_manager;
+        let block = |name| voxel_manager.block_registry.get_id(name).unwrap();
+        let (stone, dirt, grass) = (block("stone"), block("dirt"), block("grass"));
+
+        let length = MAX_MODEL_SIZE + 44;
+        for x in 0..length {
+            voxel_manager.set_voxel(cgmath::vec3(x, 0, 0), stone);
+        }
+        // Markers that would move if an axis got swapped or mirrored
+        voxel_manager.set_voxel(cgmath::vec3(0, 1, 0), dirt);
+        voxel_manager.set_voxel(cgmath::vec3(length - 1, 0, 2), grass);
+        voxel_manager.set_voxel(cgmath::vec3(5, 3, -1), dirt);
+
+        let vox = voxel_manager.export_vox(cgmath::vec3(0, 0, -1), cgmath::vec3(length - 1, 3, 2));
+        let mut data = Vec::new();
+        vox.write(&mut data).unwrap();
+        let vox = VoxFile::read(&mut data.as_slice()).unwrap();
+        assert_eq!(vox.models.len(), 2);
+        assert_eq!(vox.instances.len(), 2);
+
+        let mut imported = VoxelManger::new(
+            &world.headless.wgpu_manager.device,
+            &world.headless.wgpu_manager.queue,
+            &world.headless.wgpu_manager.config,
+            &world.headless.camera_manager.camera_bind_group_layout,
+            crate::common::state::State::depth_stencil(),
+            super::super::mesher::MeshingMode::Instanced,
+        );
+        imported.stamp_vox(&vox, cgmath::vec3(0, 0, 0), ColorMapping::Nearest);
+
+        assert_eq!(
+            normalized_voxels(&imported),
+            normalized_voxels(&world.voxel_manager)
+        );
     }
 }